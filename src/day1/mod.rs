@@ -1,47 +1,226 @@
-use std::io::{BufRead, stdin};
+use std::io::{Read, stdin};
+use std::str::FromStr;
+use anyhow::Context;
 
-#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
-struct Elf {
-    total: u32
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Elf {
+    items: Vec<u32>,
+    total: u32,
 }
 
+/// All elves of an inventory, in the order they appear in the input
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Elves(
+    Vec<Elf>
+);
+
+/// Summary statistics over the calorie totals of a group of elves
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Distribution {
+    pub min: u32,
+    pub max: u32,
+    pub median: u32,
+    pub p90: u32,
+}
+
+
+// *************************************************************************************************
+
 impl Elf {
     fn new() -> Self {
-        Self {
-            total: 0
-        }
+        Self::default()
     }
 
     fn add(&mut self, calories: u32) {
+        self.items.push(calories);
         self.total += calories;
     }
+
+    /// The calories of each individual item this elf carries
+    pub fn items(&self) -> &[u32] {
+        &self.items
+    }
+
+    pub fn total(&self) -> u32 {
+        self.total
+    }
 }
 
-pub fn main() {
-    let mut cur = Elf::new();
-    let mut all = Vec::new();
+impl FromStr for Elves {
+    type Err = anyhow::Error;
 
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut cur = Elf::new();
+        let mut all = Vec::new();
 
-    for line in stdin().lock().lines() {
-        let line = line.unwrap();
-        let line = line.trim();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
 
-        if line.is_empty() {
-            all.push(cur);
-            cur = Elf::new();
-        } else {
-            cur.add(
-                line.parse().unwrap()
-            )
+            if line.is_empty() {
+                if !cur.items.is_empty() {
+                    all.push(cur);
+                    cur = Elf::new();
+                }
+            } else {
+                cur.add(
+                    line.parse()
+                        .with_context(|| format!("line {}: invalid calorie count `{line}`", i + 1))?
+                )
+            }
         }
+
+        if !cur.items.is_empty() {
+            all.push(cur)
+        }
+
+        Ok(Self(all))
+    }
+}
+
+
+// *************************************************************************************************
+
+impl Elves {
+    pub fn iter(&self) -> impl Iterator<Item=&Elf> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The calorie totals of all elves, sorted in ascending order
+    pub fn sorted_totals(&self) -> Vec<u32> {
+        let mut totals: Vec<_> = self.iter().map(Elf::total).collect();
+        totals.sort_unstable();
+        totals
+    }
+
+    /// The sum of the `n` highest totals (or of all totals, if there are fewer than `n` elves)
+    pub fn top_total(&self, n: usize) -> u32 {
+        self.sorted_totals().iter().rev().take(n).sum()
+    }
+
+    /// The `p`-th percentile of the totals, using the nearest-rank method.
+    /// Returns `None` if there are no elves, or if `p` is not in `0.0..=100.0`
+    pub fn percentile(&self, p: f64) -> Option<u32> {
+        percentile(&self.sorted_totals(), p)
+    }
+
+    pub fn distribution(&self) -> Option<Distribution> {
+        let totals = self.sorted_totals();
+        Some(Distribution {
+            min: *totals.first()?,
+            max: *totals.last()?,
+            median: percentile(&totals, 50.0)?,
+            p90: percentile(&totals, 90.0)?,
+        })
+    }
+
+    /// All elves whose total is strictly greater than `threshold`, together with their index
+    pub fn exceeding(&self, threshold: u32) -> impl Iterator<Item=(usize, &Elf)> {
+        self.iter()
+            .enumerate()
+            .filter(move |(_, elf)| elf.total > threshold)
+    }
+}
+
+/// Nearest-rank percentile of an ascending slice
+fn percentile(sorted: &[u32], p: f64) -> Option<u32> {
+    if sorted.is_empty() || !(0.0..=100.0).contains(&p) {
+        return None;
+    }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.saturating_sub(1)])
+}
+
+
+// *************************************************************************************************
+
+#[aoc_generator(day1)]
+fn generator(input: &str) -> anyhow::Result<Elves> {
+    input.parse()
+}
+
+#[aoc(day1, part1)]
+fn part1(elves: &Elves) -> u32 {
+    elves.top_total(1)
+}
+
+#[aoc(day1, part2)]
+fn part2(elves: &Elves) -> u32 {
+    elves.top_total(3)
+}
+
+pub fn main() {
+    let mut input = String::new();
+    stdin().lock().read_to_string(&mut input).unwrap();
+    let elves: Elves = input.parse().unwrap();
+
+    println!("{}", elves.top_total(3));
+    if let Some(dist) = elves.distribution() {
+        println!("{dist:?}");
     }
+}
+
 
-    if cur.total > 0 {
-        all.push(cur)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> Elves {
+        include_str!("test.txt").parse().unwrap()
     }
 
-    all.sort();
+    #[test]
+    fn test_example() {
+        let elves = example();
+        assert_eq!(part1(&elves), 24000);
+        assert_eq!(part2(&elves), 45000);
+    }
 
-    let sum: u32 = (&all[all.len()-3..]).iter().map(|e| e.total).sum();
-    println!("{}", sum);
-}
\ No newline at end of file
+    #[test]
+    fn test_percentile() {
+        let elves = example();
+        assert_eq!(elves.percentile(0.0), Some(4000));
+        assert_eq!(elves.percentile(20.0), Some(4000));
+        assert_eq!(elves.percentile(21.0), Some(6000));
+        assert_eq!(elves.percentile(50.0), Some(10000));
+        assert_eq!(elves.percentile(100.0), Some(24000));
+
+        assert_eq!(elves.percentile(-0.1), None);
+        assert_eq!(elves.percentile(100.1), None);
+        assert_eq!(elves.percentile(f64::NAN), None);
+        assert_eq!(Elves::default().percentile(50.0), None);
+    }
+
+    #[test]
+    fn test_distribution() {
+        assert_eq!(example().distribution(), Some(Distribution {
+            min: 4000,
+            max: 24000,
+            median: 10000,
+            p90: 24000,
+        }));
+        let single: Elves = "7\n".parse().unwrap();
+        assert_eq!(single.distribution(), Some(Distribution { min: 7, max: 7, median: 7, p90: 7 }));
+        assert_eq!(Elves::default().distribution(), None);
+        assert_eq!("\n\n".parse::<Elves>().unwrap().distribution(), None);
+    }
+
+    #[test]
+    fn test_exceeding() {
+        let elves = example();
+        let indices = |threshold| elves.exceeding(threshold).map(|(i, _)| i).collect::<Vec<_>>();
+        assert_eq!(indices(10000), [2, 3]);
+        assert_eq!(indices(9999), [2, 3, 4]);
+        assert_eq!(indices(0), [0, 1, 2, 3, 4]);
+        assert!(indices(24000).is_empty());
+        assert_eq!(elves.exceeding(20000).next().unwrap().1.items(), [7000, 8000, 9000]);
+        assert_eq!(Elves::default().exceeding(0).count(), 0);
+    }
+}
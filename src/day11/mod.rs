@@ -209,7 +209,7 @@ impl Monkeys {
 
         let m = monkeys.iter()
             .map(|m| m.test.divisible_by)
-            .fold(1, |x, y| x * y);
+            .product();
 
         Self {
             monkeys,
@@ -233,7 +233,7 @@ impl Operation {
 
 impl Test {
     fn throw_target(self, item: WorryLevel) -> usize {
-        if item % self.divisible_by == 0 {
+        if item.is_multiple_of(self.divisible_by) {
             self.if_true
         } else {
             self.if_false
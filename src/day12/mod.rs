@@ -125,7 +125,7 @@ impl<T: Debug> Debug for Matrix<T> {
         for value in &self.buf {
             write!(f, " {:3?} ", value)?;
             if col >= self.columns - 1 {
-                write!(f, "\n")?;
+                writeln!(f)?;
                 col = 0;
             } else {
                 write!(f, "|")?;
@@ -174,7 +174,7 @@ impl HeightMap {
                     self.end = (col, row).into();
                     b'z'
                 } else {
-                    assert!(b'a' <= c && c <= b'z');
+                    assert!(c.is_ascii_lowercase());
                     c
                 };
                 height - b'a'
@@ -1,51 +1,631 @@
-#[aoc(day2, part1)]
-fn part1(data: &str) -> u32 {
-    data.lines()
-        .map(|line| {
-            let mut bytes = line.bytes();
-            let op = bytes.next().unwrap() - b'A';
-            bytes.next();
-            let me = bytes.next().unwrap() - b'X';
-
-            let win_or_lose = if op == me {
-                3 // draw
-            } else {
-                match (op, me) {
-                    (0, 1) => 6,
-                    (1, 2) => 6,
-                    (2, 0) => 6,
-                    (_, _) => 0,
-                }
-            };
-
-            let piece = me + 1;
-            let score = (win_or_lose + piece) as u32;
-
-
-            eprintln!("{}: {} + {} = {}", line, win_or_lose, piece, score);
-
-            score
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use anyhow::{bail, ensure, Context};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Shape {
+    Rock,
+    Paper,
+    Scissors,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    Lose,
+    Draw,
+    Win,
+}
+
+/// A single line of the strategy guide: the opponent's shape, and the second column,
+/// interpreted as `T` (either the [Shape] to play, or the desired [Outcome])
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Round<T> {
+    pub opponent: Shape,
+    pub response: T,
+}
+
+/// A round as it is actually played
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Play {
+    pub opponent: Shape,
+    pub me: Shape,
+}
+
+/// The plays of a whole strategy guide, displayed as a per-round score breakdown
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Breakdown(
+    Vec<Play>
+);
+
+/// An interpretation of the second column (`X`, `Y`, `Z`) as shapes to play
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Mapping(
+    [Shape; 3]
+);
+
+/// Index of a move in a [CyclicGame]
+pub type MoveId = usize;
+
+/// One of the moves of a [CyclicGame]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MoveSpec {
+    pub name: String,
+    /// The symbol used for this move in the first column of a strategy guide
+    pub opponent_symbol: String,
+    /// The symbol used for this move in the second column of a strategy guide
+    pub own_symbol: String,
+    /// The score for playing this move, independent of the outcome
+    pub score: u32,
+}
+
+/// A cyclic tournament with an odd number `n` of moves, in which each move beats the next
+/// `(n-1)/2` moves (wrapping around), and loses against the other `(n-1)/2`
+#[derive(Clone, Debug)]
+pub struct CyclicGame {
+    moves: Vec<MoveSpec>,
+    /// The scores for losing, drawing and winning a round, respectively
+    outcome_scores: [u32; 3],
+    /// The symbols for the desired outcomes (lose, draw, win) in the second column
+    outcome_symbols: [String; 3],
+
+    opponent_lookup: HashMap<String, MoveId>,
+    own_lookup: HashMap<String, MoveId>,
+}
+
+
+// *************************************************************************************************
+
+impl Shape {
+    pub const ALL: [Shape; 3] = [Shape::Rock, Shape::Paper, Shape::Scissors];
+
+    /// The shape that is beaten by `self`
+    pub fn beats(self) -> Shape {
+        match self {
+            Shape::Rock => Shape::Scissors,
+            Shape::Paper => Shape::Rock,
+            Shape::Scissors => Shape::Paper,
+        }
+    }
+
+    /// The shape that beats `self`
+    pub fn beaten_by(self) -> Shape {
+        match self {
+            Shape::Rock => Shape::Paper,
+            Shape::Paper => Shape::Scissors,
+            Shape::Scissors => Shape::Rock,
+        }
+    }
+
+    /// The outcome of playing `self` against `opponent`
+    pub fn against(self, opponent: Shape) -> Outcome {
+        if self == opponent {
+            Outcome::Draw
+        } else if self.beats() == opponent {
+            Outcome::Win
+        } else {
+            Outcome::Lose
+        }
+    }
+
+    pub fn score(self) -> u32 {
+        match self {
+            Shape::Rock => 1,
+            Shape::Paper => 2,
+            Shape::Scissors => 3,
+        }
+    }
+}
+
+impl Outcome {
+    pub const ALL: [Outcome; 3] = [Outcome::Lose, Outcome::Draw, Outcome::Win];
+
+    /// The shape to play against `opponent` to get this outcome
+    pub fn response_to(self, opponent: Shape) -> Shape {
+        match self {
+            Outcome::Lose => opponent.beats(),
+            Outcome::Draw => opponent,
+            Outcome::Win => opponent.beaten_by(),
+        }
+    }
+
+    pub fn score(self) -> u32 {
+        match self {
+            Outcome::Lose => 0,
+            Outcome::Draw => 3,
+            Outcome::Win => 6,
+        }
+    }
+}
+
+
+// *************************************************************************************************
+
+impl FromStr for Shape {
+    type Err = anyhow::Error;
+
+    /// Parses both the opponent's (`A`, `B`, `C`) and our own (`X`, `Y`, `Z`) symbols
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "A" | "X" => Shape::Rock,
+            "B" | "Y" => Shape::Paper,
+            "C" | "Z" => Shape::Scissors,
+            _ => bail!("Unexpected shape: `{s}`")
         })
-        .sum::<u32>()
+    }
 }
 
-#[aoc(day2, part2)]
-fn part2(data: &str) -> u32 {
-    data.lines()
-        .map(|line| {
-            let mut bytes = line.bytes();
-            let op = bytes.next().unwrap() - b'A';
-            bytes.next();
-            let tgt = bytes.next().unwrap() - b'X';
+impl FromStr for Outcome {
+    type Err = anyhow::Error;
 
-            let win_or_lose = tgt * 3;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "X" => Outcome::Lose,
+            "Y" => Outcome::Draw,
+            "Z" => Outcome::Win,
+            _ => bail!("Unexpected outcome: `{s}`")
+        })
+    }
+}
 
-            let piece = (tgt + 2 + op) % 3 + 1;
-            let score = (win_or_lose + piece) as u32;
+impl<T> FromStr for Round<T>
+    where T: FromStr<Err=anyhow::Error>
+{
+    type Err = anyhow::Error;
 
-            eprintln!("{}: {} + {} = {}", line, win_or_lose, piece, score);
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((opponent, response)) = s.trim_end().split_once(' ') else {
+            bail!("Expected two columns separated by a space, found `{s}`")
+        };
+        let opponent = match opponent {
+            "A" | "B" | "C" => opponent.parse()?,
+            _ => bail!("Unexpected opponent shape: `{opponent}`")
+        };
+        let response = match response {
+            "X" | "Y" | "Z" => response.parse()?,
+            _ => bail!("Unexpected response: `{response}`")
+        };
 
-            score
+        Ok(Self {
+            opponent,
+            response,
         })
-        .sum::<u32>()
-}
\ No newline at end of file
+    }
+}
+
+impl<T> Round<T>
+    where T: FromStr<Err=anyhow::Error>
+{
+    fn parse_guide(input: &str) -> anyhow::Result<Vec<Self>> {
+        input.lines()
+            .enumerate()
+            .map(|(i, line)| {
+                line.parse().with_context(|| format!("line {}: `{line}`", i + 1))
+            })
+            .collect()
+    }
+}
+
+impl Display for Round<Shape> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} vs {:?}", self.response, self.opponent)
+    }
+}
+
+impl Display for Round<Outcome> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} against {:?}", self.response, self.opponent)
+    }
+}
+
+
+// *************************************************************************************************
+
+impl Play {
+    pub fn outcome(&self) -> Outcome {
+        self.me.against(self.opponent)
+    }
+
+    pub fn score(&self) -> u32 {
+        self.outcome().score() + self.me.score()
+    }
+}
+
+impl Display for Play {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let win_or_lose = self.outcome().score();
+        let piece = self.me.score();
+        write!(f, "{:?} vs {:?}: {} + {} = {}", self.me, self.opponent, win_or_lose, piece, self.score())
+    }
+}
+
+impl Round<Shape> {
+    pub fn play(&self) -> Play {
+        Play {
+            opponent: self.opponent,
+            me: self.response,
+        }
+    }
+
+    pub fn outcome(&self) -> Outcome {
+        self.play().outcome()
+    }
+
+    pub fn score(&self) -> u32 {
+        self.play().score()
+    }
+}
+
+impl Round<Outcome> {
+    pub fn shape(&self) -> Shape {
+        self.response.response_to(self.opponent)
+    }
+
+    pub fn play(&self) -> Play {
+        Play {
+            opponent: self.opponent,
+            me: self.shape(),
+        }
+    }
+
+    pub fn score(&self) -> u32 {
+        self.play().score()
+    }
+}
+
+impl Breakdown {
+    pub fn plays(&self) -> &[Play] {
+        &self.0
+    }
+
+    pub fn total(&self) -> u32 {
+        self.0.iter().map(Play::score).sum()
+    }
+}
+
+impl FromIterator<Play> for Breakdown {
+    fn from_iter<I: IntoIterator<Item=Play>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl Display for Breakdown {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for play in &self.0 {
+            writeln!(f, "{play}")?;
+        }
+        write!(f, "total: {}", self.total())
+    }
+}
+
+
+// *************************************************************************************************
+
+impl Mapping {
+    /// The mapping used in part 1: `X`, `Y` and `Z` are rock, paper and scissors, respectively
+    pub const IDENTITY: Mapping = Mapping([Shape::Rock, Shape::Paper, Shape::Scissors]);
+
+    /// All 3! ways to assign the shapes to `X`, `Y` and `Z`
+    pub fn all() -> [Mapping; 6] {
+        use Shape::*;
+        [
+            Mapping([Rock, Paper, Scissors]),
+            Mapping([Rock, Scissors, Paper]),
+            Mapping([Paper, Rock, Scissors]),
+            Mapping([Paper, Scissors, Rock]),
+            Mapping([Scissors, Rock, Paper]),
+            Mapping([Scissors, Paper, Rock]),
+        ]
+    }
+
+    /// Map a second-column shape, as parsed under [Mapping::IDENTITY], to the shape to play
+    pub fn apply(&self, shape: Shape) -> Shape {
+        self.0[shape as usize]
+    }
+}
+
+impl Display for Mapping {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "X -> {:?}, Y -> {:?}, Z -> {:?}", self.0[0], self.0[1], self.0[2])
+    }
+}
+
+impl Shape {
+    /// The highest-scoring shape to play against `opponent`
+    pub fn best_response(opponent: Shape) -> Shape {
+        Self::ALL.into_iter()
+            .max_by_key(|&me| Play { opponent, me }.score())
+            .unwrap()
+    }
+}
+
+/// The maximum achievable score against a sequence of opponent moves, round by round
+pub fn optimal_play(opponents: impl IntoIterator<Item=Shape>) -> Breakdown {
+    opponents.into_iter()
+        .map(|opponent| Play { opponent, me: Shape::best_response(opponent) })
+        .collect()
+}
+
+/// The fixed interpretation of `X`, `Y` and `Z` that scores best on the given guide
+pub fn best_mapping(rounds: &[Round<Shape>]) -> (Mapping, Breakdown) {
+    Mapping::all().into_iter()
+        .map(|mapping| {
+            let breakdown = rounds.iter()
+                .map(|round| Play { opponent: round.opponent, me: mapping.apply(round.response) })
+                .collect::<Breakdown>();
+            (mapping, breakdown)
+        })
+        .max_by_key(|(_, breakdown)| breakdown.total())
+        .unwrap()
+}
+
+
+// *************************************************************************************************
+
+impl MoveSpec {
+    pub fn new(name: &str, opponent_symbol: &str, own_symbol: &str, score: u32) -> Self {
+        Self {
+            name: name.to_owned(),
+            opponent_symbol: opponent_symbol.to_owned(),
+            own_symbol: own_symbol.to_owned(),
+            score,
+        }
+    }
+}
+
+impl CyclicGame {
+    /// Create a game from its moves, listed in tournament order: each move beats the
+    /// `(moves.len() - 1) / 2` moves listed after it.
+    /// Fails if the number of moves is even or less than three (so that every outcome is
+    /// achievable), or if a symbol is used more than once within a column
+    pub fn new(moves: Vec<MoveSpec>, outcome_scores: [u32; 3], outcome_symbols: [&str; 3]) -> anyhow::Result<Self> {
+        ensure!(moves.len() % 2 == 1, "A cyclic game needs an odd number of moves, found {}", moves.len());
+        ensure!(moves.len() >= 3, "A cyclic game needs at least three moves, found {}", moves.len());
+
+        let mut opponent_lookup = HashMap::with_capacity(moves.len());
+        let mut own_lookup = HashMap::with_capacity(moves.len());
+        for (id, mov) in moves.iter().enumerate() {
+            if opponent_lookup.insert(mov.opponent_symbol.clone(), id).is_some() {
+                bail!("Opponent symbol `{}` is used more than once", mov.opponent_symbol)
+            }
+            if own_lookup.insert(mov.own_symbol.clone(), id).is_some() {
+                bail!("Own symbol `{}` is used more than once", mov.own_symbol)
+            }
+        }
+        for (i, symbol) in outcome_symbols.iter().enumerate() {
+            ensure!(!outcome_symbols[..i].contains(symbol), "Outcome symbol `{symbol}` is used more than once");
+        }
+
+        Ok(Self {
+            moves,
+            outcome_scores,
+            outcome_symbols: outcome_symbols.map(str::to_owned),
+            opponent_lookup,
+            own_lookup,
+        })
+    }
+
+    /// Rock-Paper-Scissors, with the puzzle's symbols and scores
+    pub fn classic() -> Self {
+        Self::new(
+            vec![
+                MoveSpec::new("Scissors", "C", "Z", 3),
+                MoveSpec::new("Paper", "B", "Y", 2),
+                MoveSpec::new("Rock", "A", "X", 1),
+            ],
+            Outcome::ALL.map(Outcome::score),
+            ["X", "Y", "Z"],
+        ).unwrap()
+    }
+
+    /// Rock-Paper-Scissors-Lizard-Spock. The opponent uses `A` to `E`, we use `V` to `Z`
+    /// (both in the order Rock, Paper, Scissors, Lizard, Spock); outcomes remain `X`, `Y`, `Z`
+    pub fn rock_paper_scissors_lizard_spock() -> Self {
+        Self::new(
+            vec![
+                MoveSpec::new("Scissors", "C", "X", 3),
+                MoveSpec::new("Lizard", "D", "Y", 4),
+                MoveSpec::new("Paper", "B", "W", 2),
+                MoveSpec::new("Spock", "E", "Z", 5),
+                MoveSpec::new("Rock", "A", "V", 1),
+            ],
+            Outcome::ALL.map(Outcome::score),
+            ["X", "Y", "Z"],
+        ).unwrap()
+    }
+
+    pub fn moves(&self) -> &[MoveSpec] {
+        &self.moves
+    }
+
+    /// The outcome of playing `me` against `opponent`
+    pub fn outcome(&self, me: MoveId, opponent: MoveId) -> Outcome {
+        let n = self.moves.len();
+        match (opponent + n - me) % n {
+            0 => Outcome::Draw,
+            d if d <= n / 2 => Outcome::Win,
+            _ => Outcome::Lose,
+        }
+    }
+
+    pub fn outcome_score(&self, outcome: Outcome) -> u32 {
+        self.outcome_scores[outcome as usize]
+    }
+
+    pub fn score(&self, me: MoveId, opponent: MoveId) -> u32 {
+        self.outcome_score(self.outcome(me, opponent)) + self.moves[me].score
+    }
+
+    /// The highest-scoring move that achieves `outcome` against `opponent`
+    pub fn response_to(&self, opponent: MoveId, outcome: Outcome) -> MoveId {
+        (0..self.moves.len())
+            .filter(|&me| self.outcome(me, opponent) == outcome)
+            .max_by_key(|&me| self.moves[me].score)
+            .expect("every outcome is achievable in a cyclic game")
+    }
+}
+
+
+// *************************************************************************************************
+
+impl CyclicGame {
+    fn lookup_opponent(&self, symbol: &str) -> anyhow::Result<MoveId> {
+        self.opponent_lookup.get(symbol).copied()
+            .with_context(|| format!("Unexpected opponent symbol: `{symbol}`"))
+    }
+
+    fn lookup_own(&self, symbol: &str) -> anyhow::Result<MoveId> {
+        self.own_lookup.get(symbol).copied()
+            .with_context(|| format!("Unexpected own symbol: `{symbol}`"))
+    }
+
+    fn lookup_outcome(&self, symbol: &str) -> anyhow::Result<Outcome> {
+        self.outcome_symbols.iter()
+            .position(|s| s == symbol)
+            .map(|i| Outcome::ALL[i])
+            .with_context(|| format!("Unexpected outcome symbol: `{symbol}`"))
+    }
+
+    fn parse_line<T>(&self, line: &str, response: impl Fn(&str) -> anyhow::Result<T>) -> anyhow::Result<(MoveId, T)> {
+        let Some((opponent, own)) = line.trim_end().split_once(' ') else {
+            bail!("Expected two columns separated by a space, found `{line}`")
+        };
+        Ok((self.lookup_opponent(opponent)?, response(own)?))
+    }
+
+    fn parse_guide<T>(&self, input: &str, response: impl Fn(&str) -> anyhow::Result<T>) -> anyhow::Result<Vec<(MoveId, T)>> {
+        input.lines()
+            .enumerate()
+            .map(|(i, line)| {
+                self.parse_line(line, &response).with_context(|| format!("line {}: `{line}`", i + 1))
+            })
+            .collect()
+    }
+
+    /// Total score of a strategy guide, where the second column is the move to play
+    pub fn score_moves(&self, input: &str) -> anyhow::Result<u32> {
+        let rounds = self.parse_guide(input, |s| self.lookup_own(s))?;
+        Ok(rounds.into_iter()
+            .map(|(opponent, me)| self.score(me, opponent))
+            .sum())
+    }
+
+    /// Total score of a strategy guide, where the second column is the desired outcome
+    pub fn score_outcomes(&self, input: &str) -> anyhow::Result<u32> {
+        let rounds = self.parse_guide(input, |s| self.lookup_outcome(s))?;
+        Ok(rounds.into_iter()
+            .map(|(opponent, outcome)| self.score(self.response_to(opponent, outcome), opponent))
+            .sum())
+    }
+}
+
+
+// *************************************************************************************************
+
+#[aoc(day2, part1)]
+fn part1(data: &str) -> anyhow::Result<u32> {
+    let rounds = Round::<Shape>::parse_guide(data)?;
+    let guide: Breakdown = rounds.iter().map(Round::<Shape>::play).collect();
+    let optimal = optimal_play(rounds.iter().map(|r| r.opponent));
+    let (mapping, mapped) = best_mapping(&rounds);
+
+    eprintln!("{guide}\n");
+    eprintln!("optimal: {}", optimal.total());
+    eprintln!("best mapping ({mapping}): {}", mapped.total());
+
+    Ok(guide.total())
+}
+
+#[aoc(day2, part2)]
+fn part2(data: &str) -> anyhow::Result<u32> {
+    let rounds = Round::<Outcome>::parse_guide(data)?;
+    let guide: Breakdown = rounds.iter().map(Round::<Outcome>::play).collect();
+    let optimal = optimal_play(rounds.iter().map(|r| r.opponent));
+
+    eprintln!("{guide}\n");
+    eprintln!("optimal: {}", optimal.total());
+
+    Ok(guide.total())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_example() {
+        assert_eq!(part1(include_str!("test.txt")).unwrap(), 15);
+        assert_eq!(part2(include_str!("test.txt")).unwrap(), 12);
+    }
+
+    #[test]
+    fn test_malformed() {
+        for line in ["A A", "A", "D X", "AX"] {
+            assert!(line.parse::<Round<Shape>>().is_err(), "{line}");
+            assert!(line.parse::<Round<Outcome>>().is_err(), "{line}");
+        }
+        assert!(Round::<Shape>::parse_guide("A Y\nA A\n").is_err());
+    }
+
+    #[test]
+    fn test_optimal() {
+        let rounds = Round::<Shape>::parse_guide(include_str!("test.txt")).unwrap();
+        assert_eq!(optimal_play(rounds.iter().map(|r| r.opponent)).total(), 24);
+
+        let (mapping, breakdown) = best_mapping(&rounds);
+        assert_eq!(breakdown.total(), 24);
+        assert_eq!(mapping, Mapping([Shape::Scissors, Shape::Paper, Shape::Rock]));
+    }
+
+    #[test]
+    fn test_classic() {
+        let game = CyclicGame::classic();
+        assert_eq!(game.score_moves(include_str!("test.txt")).unwrap(), 15);
+        assert_eq!(game.score_outcomes(include_str!("test.txt")).unwrap(), 12);
+    }
+
+    #[test]
+    fn test_lizard_spock() {
+        let game = CyclicGame::rock_paper_scissors_lizard_spock();
+        let id = |name| game.moves().iter().position(|m| m.name == name).unwrap();
+        let beats = [
+            ("Scissors", "Paper"), ("Paper", "Rock"), ("Rock", "Lizard"), ("Lizard", "Spock"),
+            ("Spock", "Scissors"), ("Scissors", "Lizard"), ("Lizard", "Paper"), ("Paper", "Spock"),
+            ("Spock", "Rock"), ("Rock", "Scissors"),
+        ];
+        for me in 0..game.moves().len() {
+            for opponent in 0..game.moves().len() {
+                let expected = if me == opponent {
+                    Outcome::Draw
+                } else if beats.contains(&(game.moves()[me].name.as_str(), game.moves()[opponent].name.as_str())) {
+                    Outcome::Win
+                } else {
+                    Outcome::Lose
+                };
+                assert_eq!(game.outcome(me, opponent), expected, "{} vs {}", game.moves()[me].name, game.moves()[opponent].name);
+            }
+        }
+        assert_eq!(game.score_moves("A V\nE W\nD X\n").unwrap(), (1 + 3) + (2 + 6) + (3 + 6));
+        assert_eq!(game.response_to(id("Spock"), Outcome::Win), id("Lizard"));
+    }
+
+    #[test]
+    fn test_invalid_games() {
+        let moves = || vec![
+            MoveSpec::new("Rock", "A", "X", 1),
+            MoveSpec::new("Paper", "B", "Y", 2),
+            MoveSpec::new("Scissors", "C", "Z", 3),
+        ];
+        let scores = Outcome::ALL.map(Outcome::score);
+        assert!(CyclicGame::new(moves(), scores, ["X", "Y", "Z"]).is_ok());
+        assert!(CyclicGame::new(moves(), scores, ["X", "Y", "X"]).is_err());
+        assert!(CyclicGame::new(moves()[..2].to_vec(), scores, ["X", "Y", "Z"]).is_err());
+        // a single move can only draw
+        assert!(CyclicGame::new(moves()[..1].to_vec(), scores, ["X", "Y", "Z"]).is_err());
+
+        let mut duplicate = moves();
+        duplicate[2].opponent_symbol = "A".to_owned();
+        assert!(CyclicGame::new(duplicate, scores, ["X", "Y", "Z"]).is_err());
+    }
+}
@@ -1,81 +1,468 @@
-fn to_priority(x: u8) -> u8 {
-    if b'a' <= x && x <= b'z' {
-        x - b'a'
-    } else if b'A' <= x && x <= b'Z' {
-        x - b'A' + 26
-    } else {
-        panic!("invalid input");
-    }
-}
-
-fn from_priority(x: u8) -> char {
-    (
-        if x >= 26 {
-            x - 26 + b'A'
-        } else {
-            x + b'a'
-        }
-    ) as char
+use std::fmt::{Debug, Display, Formatter};
+use std::ops::{BitAnd, BitOr};
+use std::str::FromStr;
+use anyhow::{bail, ensure, Context};
+
+
+/// An item type, represented by its priority (`a..=z` are 1 to 26, `A..=Z` are 27 to 52)
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Item(u8);
+
+/// A set of item types, as a bitset where bit `p` is set iff the item with priority `p` is present
+#[derive(Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub struct ItemSet(u64);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Rucksack {
+    items: Vec<Item>,
 }
 
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Compartment {
+    First,
+    Second,
+}
+
+/// Move `count` items of type `item` out of compartment `from`, into the other compartment
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Relocation {
+    pub item: Item,
+    pub count: usize,
+    pub from: Compartment,
+}
+
+/// How often each item type is misplaced, across a collection of rucksacks
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MisplacedReport {
+    /// `(item, rucksacks, items)`: the number of rucksacks in which `item` is misplaced, and the
+    /// total number of items of that type that need to be moved.
+    /// Sorted with the most frequently misplaced item types first
+    entries: Vec<(Item, usize, usize)>,
+}
 
-#[aoc(day3, part1)]
-fn part1(input: &str) -> u32 {
-    input
-        .lines()
-        .map(|line| {
-            let half = line.len() / 2;
 
-            println!("{}: {}_{}", line, &line[..half], &line[half..]);
-            let line = line.as_bytes();
+// *************************************************************************************************
 
+impl Item {
+    pub fn priority(self) -> u32 {
+        self.0 as u32
+    }
 
-            let mut map = [false; 52];
-            for &c in &line[..half] {
-                map[to_priority(c) as usize] = true;
+    pub fn as_char(self) -> char {
+        (
+            if self.0 > 26 {
+                self.0 - 27 + b'A'
+            } else {
+                self.0 - 1 + b'a'
             }
+        ) as char
+    }
+}
 
-            let mut map2 = [false; 52];
-            let mut total = 0;
-            for &c in &line[half..] {
-                let p = to_priority(c);
-                if map[p as usize] && !map2[p as usize] {
-                    println!("  {}: {}", c as char, p + 1);
-                    map2[p as usize] = true;
-                    total += p as u32 + 1;
-                }
+impl TryFrom<u8> for Item {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(Self(
+            if value.is_ascii_lowercase() {
+                value - b'a' + 1
+            } else if value.is_ascii_uppercase() {
+                value - b'A' + 27
+            } else {
+                bail!("Unexpected item: {value} ('{}')", value as char)
             }
+        ))
+    }
+}
+
+impl Display for Item {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.as_char(), f)
+    }
+}
+
+impl Debug for Item {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}({})", self.as_char(), self.0)
+    }
+}
+
+
+// *************************************************************************************************
+
+impl ItemSet {
+    pub const EMPTY: ItemSet = ItemSet(0);
+    /// The set containing every item type
+    pub const ALL: ItemSet = ItemSet(((1 << 52) - 1) << 1);
+
+    pub fn insert(&mut self, item: Item) {
+        self.0 |= 1 << item.0;
+    }
+
+    pub fn contains(self, item: Item) -> bool {
+        self.0 & (1 << item.0) != 0
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    pub fn len(self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Iterate over the items in the set, in order of increasing priority
+    pub fn iter(self) -> impl Iterator<Item=Item> {
+        (1..=52)
+            .map(Item)
+            .filter(move |&item| self.contains(item))
+    }
+
+    pub fn priority_sum(self) -> u32 {
+        self.iter().map(Item::priority).sum()
+    }
+}
+
+impl BitOr for ItemSet {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        self.union(rhs)
+    }
+}
+
+impl BitAnd for ItemSet {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self::Output {
+        self.intersection(rhs)
+    }
+}
+
+impl FromIterator<Item> for ItemSet {
+    fn from_iter<T: IntoIterator<Item=Item>>(iter: T) -> Self {
+        let mut set = Self::EMPTY;
+        for item in iter {
+            set.insert(item)
+        }
+        set
+    }
+}
+
+impl<'a> FromIterator<&'a Item> for ItemSet {
+    fn from_iter<T: IntoIterator<Item=&'a Item>>(iter: T) -> Self {
+        iter.into_iter().copied().collect()
+    }
+}
+
+impl Debug for ItemSet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+
+// *************************************************************************************************
+
+impl FromStr for Rucksack {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let items: Vec<Item> =
+            s.bytes()
+                .map(Item::try_from)
+                .collect::<Result<_, _>>()?;
+        ensure!(items.len().is_multiple_of(2), "Rucksack has an odd number of items ({})", items.len());
+
+        Ok(Self { items })
+    }
+}
+
+impl Rucksack {
+    fn parse_all(input: &str) -> anyhow::Result<Vec<Self>> {
+        input.lines()
+            .enumerate()
+            .map(|(i, line)| {
+                line.trim_end().parse().with_context(|| format!("line {}: `{line}`", i + 1))
+            })
+            .collect()
+    }
 
-            println!("total (line): {}\n", total);
-            total
+    pub fn items(&self) -> &[Item] {
+        &self.items
+    }
+
+    /// The items of both compartments, as slices
+    pub fn compartments(&self) -> (&[Item], &[Item]) {
+        self.items.split_at(self.items.len() / 2)
+    }
+
+    pub fn item_set(&self) -> ItemSet {
+        self.items.iter().collect()
+    }
+
+    /// The item types that appear in both compartments
+    pub fn shared(&self) -> ItemSet {
+        let (left, right) = self.compartments();
+        left.iter().collect::<ItemSet>() & right.iter().collect::<ItemSet>()
+    }
+}
+
+/// For each consecutive group of `group_size` rucksacks, the item types common to the whole group.
+/// Fails if the number of rucksacks is not a multiple of `group_size`
+pub fn badges(rucksacks: &[Rucksack], group_size: usize) -> anyhow::Result<Vec<ItemSet>> {
+    ensure!(group_size > 0, "Group size must be positive");
+    ensure!(
+        rucksacks.len().is_multiple_of(group_size),
+        "The last group is incomplete: {} rucksacks cannot be split into groups of {group_size}",
+        rucksacks.len()
+    );
+
+    Ok(rucksacks
+        .chunks(group_size)
+        .map(|group| {
+            group.iter()
+                .map(Rucksack::item_set)
+                .fold(ItemSet::ALL, ItemSet::intersection)
         })
-        .sum()
+        .collect())
 }
 
-#[aoc(day3, part2)]
-fn part2(input: &str) -> usize {
-    let mut index = 0;
-    let mut map = [[false; 52]; 3];
-    let mut total = 0;
-
-    for line in input.lines() {
-        println!("{}", line);
-        for &c in line.as_bytes() {
-            map[index][to_priority(c) as usize] = true;
+
+// *************************************************************************************************
+
+impl Compartment {
+    pub fn other(self) -> Self {
+        match self {
+            Compartment::First => Compartment::Second,
+            Compartment::Second => Compartment::First,
         }
-        index += 1;
-        if index >= 3 {
-            for i in 0..52 {
-                if map[0][i] && map[1][i] && map[2][i] {
-                    let p = i + 1;
-                    println!(" {}:{}", from_priority(i as u8), p);
-                    total += p;
+    }
+}
+
+impl Display for Relocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "move {} x {} from {:?} to {:?}", self.count, self.item, self.from, self.from.other())
+    }
+}
+
+/// Number of items of each type in `items`, indexed by priority
+fn count_items(items: &[Item]) -> [usize; 53] {
+    let mut counts = [0; 53];
+    for item in items {
+        counts[item.0 as usize] += 1;
+    }
+    counts
+}
+
+impl Rucksack {
+    /// The minimal relocations after which no item type appears in both compartments.
+    ///
+    /// Each shared item type is handled independently, by moving all its items out of the
+    /// compartment that holds fewer of them (out of the second one, on a tie).
+    /// Note that this may leave the compartments with different sizes
+    pub fn plan(&self) -> Vec<Relocation> {
+        let (first, second) = self.compartments();
+        let first_counts = count_items(first);
+        let second_counts = count_items(second);
+
+        self.shared()
+            .iter()
+            .map(|item| {
+                let in_first = first_counts[item.0 as usize];
+                let in_second = second_counts[item.0 as usize];
+                if in_first < in_second {
+                    Relocation { item, count: in_first, from: Compartment::First }
+                } else {
+                    Relocation { item, count: in_second, from: Compartment::Second }
                 }
+            })
+            .collect()
+    }
+}
+
+impl MisplacedReport {
+    pub fn new(rucksacks: &[Rucksack]) -> Self {
+        let mut rucksack_counts = [0; 53];
+        let mut item_counts = [0; 53];
+        for relocation in rucksacks.iter().flat_map(Rucksack::plan) {
+            rucksack_counts[relocation.item.0 as usize] += 1;
+            item_counts[relocation.item.0 as usize] += relocation.count;
+        }
+
+        let mut entries: Vec<_> =
+            ItemSet::ALL.iter()
+                .map(|item| (item, rucksack_counts[item.0 as usize], item_counts[item.0 as usize]))
+                .filter(|&(_, rucksacks, _)| rucksacks > 0)
+                .collect();
+        entries.sort_by(|(_, r1, i1), (_, r2, i2)| (r2, i2).cmp(&(r1, i1)));
+
+        Self { entries }
+    }
+
+    /// All misplaced item types, most frequently misplaced first
+    pub fn entries(&self) -> &[(Item, usize, usize)] {
+        &self.entries
+    }
+
+    /// The `n` most frequently misplaced item types
+    pub fn most_frequent(&self, n: usize) -> impl Iterator<Item=Item> + '_ {
+        self.entries.iter().take(n).map(|(item, _, _)| *item)
+    }
+}
+
+impl Display for MisplacedReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (item, rucksacks, items) in &self.entries {
+            writeln!(f, "{item}: misplaced in {rucksacks} rucksack(s), {items} item(s) to move")?;
+        }
+        Ok(())
+    }
+}
+
+
+// *************************************************************************************************
+
+#[aoc(day3, part1)]
+fn part1(input: &str) -> anyhow::Result<u32> {
+    Ok(Rucksack::parse_all(input)?
+        .iter()
+        .map(|rucksack| {
+            let shared = rucksack.shared();
+            println!("{:?}: {shared:?}", rucksack.compartments());
+            for relocation in rucksack.plan() {
+                println!("  {relocation}");
             }
-            index = 0;
-            map = [[false; 52]; 3];
+            shared.priority_sum()
+        })
+        .sum())
+}
+
+#[aoc(day3, part2)]
+fn part2(input: &str) -> anyhow::Result<u32> {
+    let rucksacks = Rucksack::parse_all(input)?;
+    Ok(badges(&rucksacks, 3)?
+        .into_iter()
+        .inspect(|badge| println!("{badge:?}"))
+        .map(ItemSet::priority_sum)
+        .sum())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(c: char) -> Item {
+        Item::try_from(c as u8).unwrap()
+    }
+
+    fn set(items: &str) -> ItemSet {
+        items.chars().map(item).collect()
+    }
+
+    #[test]
+    fn test_example() {
+        assert_eq!(part1(include_str!("test.txt")).unwrap(), 157);
+        assert_eq!(part2(include_str!("test.txt")).unwrap(), 70);
+    }
+
+    #[test]
+    fn test_item_set() {
+        assert_eq!(item('a').priority(), 1);
+        assert_eq!(item('Z').priority(), 52);
+        assert_eq!(item('Q').to_string(), "Q");
+        assert!(Item::try_from(b'1').is_err());
+
+        let abc = set("abcab");
+        assert_eq!(abc.len(), 3);
+        assert!(abc.contains(item('b')) && !abc.contains(item('B')));
+        assert_eq!(abc & set("bCc"), set("bc"));
+        assert_eq!(abc | set("Z"), set("abcZ"));
+        assert_eq!(set("zAa").iter().collect::<Vec<_>>(), [item('a'), item('z'), item('A')]);
+        assert_eq!(set("aZ").priority_sum(), 53);
+        assert!(ItemSet::EMPTY.is_empty());
+        assert_eq!(ItemSet::ALL.len(), 52);
+        assert_eq!(ItemSet::ALL & abc, abc);
+    }
+
+    #[test]
+    fn test_rucksack() {
+        let rucksack: Rucksack = "vJrwpWtwJgWrhcsFMMfFFhFp".parse().unwrap();
+        assert_eq!(rucksack.compartments().0.len(), 12);
+        assert_eq!(rucksack.shared(), set("p"));
+        assert_eq!("".parse::<Rucksack>().unwrap().shared(), ItemSet::EMPTY);
+
+        assert!("abc".parse::<Rucksack>().is_err());
+        assert!("ab1c".parse::<Rucksack>().is_err());
+        assert!("ab c".parse::<Rucksack>().is_err());
+        assert!(Rucksack::parse_all("ab\nabc\n").is_err());
+    }
+
+    #[test]
+    fn test_badges() {
+        let rucksacks = Rucksack::parse_all(include_str!("test.txt")).unwrap();
+        assert_eq!(badges(&rucksacks, 3).unwrap(), [set("r"), set("Z")]);
+        assert_eq!(badges(&rucksacks, 1).unwrap()[0], rucksacks[0].item_set());
+        assert_eq!(badges(&rucksacks, 6).unwrap().len(), 1);
+        assert!(badges(&[], 3).unwrap().is_empty());
+
+        assert!(badges(&rucksacks, 4).is_err());
+        assert!(badges(&rucksacks[..5], 3).is_err());
+        assert!(badges(&rucksacks, 0).is_err());
+    }
+
+    #[test]
+    fn test_plan() {
+        for rucksack in Rucksack::parse_all(include_str!("test.txt")).unwrap().iter()
+            .chain(&["aaba".parse().unwrap(), "abab".parse().unwrap(), "aBcdBaaB".parse().unwrap()]) {
+            let (first, second) = rucksack.compartments();
+            let (mut first, mut second) = (first.to_vec(), second.to_vec());
+            for relocation in rucksack.plan() {
+                let (from, to) = match relocation.from {
+                    Compartment::First => (&mut first, &mut second),
+                    Compartment::Second => (&mut second, &mut first),
+                };
+                let before = from.len();
+                from.retain(|&item| item != relocation.item);
+                assert_eq!(before - from.len(), relocation.count, "{relocation}");
+                to.extend(std::iter::repeat_n(relocation.item, relocation.count));
+            }
+            let first: ItemSet = first.iter().collect();
+            let second: ItemSet = second.iter().collect();
+            assert!((first & second).is_empty(), "{rucksack:?}");
+            assert_eq!(first | second, rucksack.item_set());
         }
+
+        let rucksack: Rucksack = "aBcdBaaB".parse().unwrap();
+        assert_eq!(rucksack.plan(), [
+            Relocation { item: item('a'), count: 1, from: Compartment::First },
+            Relocation { item: item('B'), count: 1, from: Compartment::First },
+        ]);
     }
 
-    total
-}
\ No newline at end of file
+    #[test]
+    fn test_misplaced_report() {
+        let rucksacks: Vec<Rucksack> = ["aaba", "abab", "cccc", "dd", "xy"].iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let report = MisplacedReport::new(&rucksacks);
+        assert_eq!(report.entries(), [
+            (item('a'), 2, 2),
+            (item('c'), 1, 2),
+            (item('b'), 1, 1),
+            (item('d'), 1, 1),
+        ]);
+        assert_eq!(report.most_frequent(2).collect::<Vec<_>>(), [item('a'), item('c')]);
+        assert_eq!(report.to_string().lines().next(), Some("a: misplaced in 2 rucksack(s), 2 item(s) to move"));
+        assert!(MisplacedReport::new(&rucksacks[4..]).entries().is_empty());
+    }
+}
@@ -1,67 +1,426 @@
+use std::fmt::{Debug, Display, Formatter};
+use std::ops::{Add, Sub};
+use anyhow::Context;
 use regex::Regex;
 
-pub fn part1() {
-    let re = Regex::new("^(\\d+)-(\\d+),(\\d+)-(\\d+)$").unwrap();
 
-    let c =
-        include_str!("./input.txt")
-            .lines()
-            .filter(|line| {
-                println!("{}", line);
-                let digits: [u32; 4] = {
-                    let captures = re.captures(line).unwrap();
-                    println!("{:?}", captures);
-                    [
-                        &captures[1],
-                        &captures[2],
-                        &captures[3],
-                        &captures[4],
-                    ].map(|c| c.parse().unwrap())
-                };
-
-                let overlap =
-                    (digits[0] <= digits[2] && digits[1] >= digits[3])
-                        || (digits[0] >= digits[2] && digits[1] <= digits[3]);
-                println!("Overlap: {}", overlap);
-                overlap
+/// A non-empty, closed interval `[start, end]`
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Interval<T> {
+    start: T,
+    end: T,
+}
+
+/// A set of values, stored as a sorted list of disjoint intervals.
+/// Only overlapping intervals are merged: `T` need not be an integer type, so adjacent
+/// integer intervals such as `[1, 2]` and `[3, 4]` are kept apart
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IntervalSet<T> {
+    intervals: Vec<Interval<T>>,
+}
+
+/// How many elves cover each section, as maximal segments of sections covered by the same
+/// number of elves. Only sections between the lowest and highest assigned section are included
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Coverage {
+    segments: Vec<(Interval<u32>, usize)>,
+}
+
+
+// *************************************************************************************************
+
+impl<T: Copy + Ord> Interval<T> {
+    /// Returns `None` if `start > end`
+    pub fn new(start: T, end: T) -> Option<Self> {
+        if start <= end {
+            Some(Self { start, end })
+        } else {
+            None
+        }
+    }
+
+    /// The interval containing only `value`
+    pub fn singleton(value: T) -> Self {
+        Self { start: value, end: value }
+    }
+
+    pub fn start(&self) -> T {
+        self.start
+    }
+
+    pub fn end(&self) -> T {
+        self.end
+    }
+
+    pub fn contains_value(&self, value: T) -> bool {
+        self.start <= value && value <= self.end
+    }
+
+    /// Whether `other` is fully contained in `self`
+    pub fn contains(&self, other: &Self) -> bool {
+        self.start <= other.start && other.end <= self.end
+    }
+
+    pub fn overlaps(&self, other: &Self) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+
+    /// The values in both intervals, or `None` if they do not overlap
+    pub fn intersection(&self, other: &Self) -> Option<Self> {
+        Self::new(self.start.max(other.start), self.end.min(other.end))
+    }
+
+    /// The values in either interval, or `None` if they do not overlap
+    /// (in which case the union is not an interval)
+    pub fn union(&self, other: &Self) -> Option<Self> {
+        if self.overlaps(other) {
+            Some(Self {
+                start: self.start.min(other.start),
+                end: self.end.max(other.end),
             })
-            .count();
+        } else {
+            None
+        }
+    }
+}
+
+impl<T> Interval<T>
+    where T: Copy + Ord + Add<Output=T> + Sub<Output=T> + From<u8>
+{
+    /// The number of (integer) values in the interval.
+    /// Overflows if the interval covers all values of `T`, use a wider `T` for that
+    pub fn len(&self) -> T {
+        self.end - self.start + T::from(1)
+    }
+}
+
+impl<T: Display> Display for Interval<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+impl<T: Debug> Debug for Interval<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{:?}, {:?}]", self.start, self.end)
+    }
+}
+
+
+// *************************************************************************************************
+
+impl<T> Default for IntervalSet<T> {
+    fn default() -> Self {
+        Self { intervals: Vec::new() }
+    }
+}
+
+impl<T: Copy + Ord> IntervalSet<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The disjoint intervals making up this set, sorted by their start
+    pub fn intervals(&self) -> &[Interval<T>] {
+        &self.intervals
+    }
+
+    /// Adds all values of `interval`, merging it with any intervals it overlaps
+    /// (but not with intervals that are merely adjacent to it)
+    pub fn insert(&mut self, interval: Interval<T>) {
+        // the first interval that ends at or after the start of the new one
+        let first = self.intervals.partition_point(|i| i.end < interval.start);
+        // one past the last interval that starts at or before the end of the new one
+        let last = self.intervals.partition_point(|i| i.start <= interval.end);
+
+        let merged = self.intervals[first..last]
+            .iter()
+            .fold(interval, |acc, i| acc.union(i).unwrap());
+        self.intervals.splice(first..last, [merged]);
+    }
+
+    pub fn contains_value(&self, value: T) -> bool {
+        let index = self.intervals.partition_point(|i| i.end < value);
+        self.intervals.get(index).is_some_and(|i| i.contains_value(value))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.intervals.is_empty()
+    }
+}
+
+impl<T> IntervalSet<T>
+    where T: Copy + Ord + Add<Output=T> + Sub<Output=T> + From<u8>
+{
+    /// The number of (integer) values in the set
+    pub fn len(&self) -> T {
+        self.intervals.iter()
+            .map(Interval::len)
+            .fold(T::from(0), |x, y| x + y)
+    }
+}
+
+impl<T: Copy + Ord> FromIterator<Interval<T>> for IntervalSet<T> {
+    fn from_iter<I: IntoIterator<Item=Interval<T>>>(iter: I) -> Self {
+        let mut set = Self::new();
+        for interval in iter {
+            set.insert(interval);
+        }
+        set
+    }
+}
+
+
+impl Coverage {
+    /// Sweep over the start and end points of all `assignments`
+    pub fn new(assignments: impl IntoIterator<Item=Interval<u32>>) -> Self {
+        // (section, change in the number of covering elves), where an assignment stops
+        // covering at `end + 1`; `u64` so that this cannot overflow
+        let mut events: Vec<(u64, isize)> =
+            assignments.into_iter()
+                .flat_map(|i| [(i.start() as u64, 1), (i.end() as u64 + 1, -1)])
+                .collect();
+        events.sort_unstable();
 
-    println!("{}", c)
+        let mut segments = Vec::new();
+        let mut count = 0;
+        let mut events = events.into_iter().peekable();
+        while let Some((pos, delta)) = events.next() {
+            count += delta;
+            match events.peek() {
+                Some(&(next, _)) if next > pos => {
+                    let segment = Interval::new(pos as u32, (next - 1) as u32).unwrap();
+                    segments.push((segment, count as usize));
+                }
+                _ => {}
+            }
+        }
+
+        Self { segments }
+    }
+
+    /// The sections between the lowest and highest assigned one, with the number of elves covering
+    /// them
+    pub fn segments(&self) -> &[(Interval<u32>, usize)] {
+        &self.segments
+    }
+
+    /// The number of sections in segments whose count satisfies `pred`; a `u64`, since all 2^32
+    /// sections may be covered
+    fn count_sections(&self, pred: impl Fn(usize) -> bool) -> u64 {
+        self.segments.iter()
+            .filter(|(_, count)| pred(*count))
+            .map(|(segment, _)| Interval::new(segment.start() as u64, segment.end() as u64).unwrap().len())
+            .sum()
+    }
+
+    /// The number of sections covered by at least `k` elves
+    pub fn at_least(&self, k: usize) -> u64 {
+        self.count_sections(|count| count >= k)
+    }
+
+    /// The number of sections covered by exactly `k` elves
+    pub fn exactly(&self, k: usize) -> u64 {
+        self.count_sections(|count| count == k)
+    }
+
+    /// The sections that no elf covers, between the lowest and highest assigned section
+    pub fn uncovered(&self) -> IntervalSet<u32> {
+        self.segments.iter()
+            .filter(|(_, count)| *count == 0)
+            .map(|(segment, _)| *segment)
+            .collect()
+    }
 }
 
-pub fn part2() {
+/// For each assignment, the indices of the other assignments that fully contain it
+pub fn containing(assignments: &[Interval<u32>]) -> Vec<Vec<usize>> {
+    let mut by_start: Vec<_> = (0..assignments.len()).collect();
+    by_start.sort_by_key(|&i| assignments[i].start());
+
+    assignments.iter()
+        .enumerate()
+        .map(|(i, assignment)| {
+            // only assignments that start no later than this one can contain it
+            let candidates = by_start.partition_point(|&j| assignments[j].start() <= assignment.start());
+            let mut containers: Vec<_> =
+                by_start[..candidates].iter()
+                    .copied()
+                    .filter(|&j| j != i && assignments[j].contains(assignment))
+                    .collect();
+            containers.sort_unstable();
+            containers
+        })
+        .collect()
+}
+
+
+// *************************************************************************************************
+
+type Pair = (Interval<u32>, Interval<u32>);
+
+fn parse_pair(re: &Regex, line: &str) -> anyhow::Result<Pair> {
+    let captures = re.captures(line)
+        .context("expected a line of the form `a-b,c-d`")?;
+    let [a, b, c, d] = [1, 2, 3, 4]
+        .map(|i| captures[i].parse::<u32>());
+
+    let interval = |start: u32, end: u32| {
+        Interval::new(start, end)
+            .with_context(|| format!("`{start}-{end}` is not a valid range"))
+    };
+    Ok((interval(a?, b?)?, interval(c?, d?)?))
+}
+
+#[aoc_generator(day4)]
+fn generator(input: &str) -> anyhow::Result<Vec<Pair>> {
     let re = Regex::new("^(\\d+)-(\\d+),(\\d+)-(\\d+)$").unwrap();
 
-    let c =
-        include_str!("./input.txt")
-            .lines()
-            .filter(|line| {
-                println!("{}", line);
-                let digits: [u32; 4] = {
-                    let captures = re.captures(line).unwrap();
-                    println!("{:?}", captures);
-                    [
-                        &captures[1],
-                        &captures[2],
-                        &captures[3],
-                        &captures[4],
-                    ].map(|c| c.parse().unwrap())
-                };
-
-                let overlap =
-                    (digits[0] <= digits[2] && digits[2] <= digits[1])
-                        || (digits[0] <= digits[3] && digits[3] <= digits[1])
-                        || (digits[2] <= digits[0] && digits[0] <= digits[3])
-                        || (digits[2] <= digits[1] && digits[1] <= digits[3]);
-                println!("Overlap: {}", overlap);
-                overlap
-            })
-            .count();
+    input
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            parse_pair(&re, line.trim_end())
+                .with_context(|| format!("line {}: `{line}`", i + 1))
+        })
+        .collect()
+}
+
+/// The assignments of all elves; the elves of pair `i` have index `2*i` and `2*i + 1`
+fn elves(pairs: &[Pair]) -> Vec<Interval<u32>> {
+    pairs.iter()
+        .flat_map(|&(a, b)| [a, b])
+        .collect()
+}
+
+#[aoc(day4, part1)]
+fn part1(pairs: &[Pair]) -> usize {
+    pairs.iter()
+        .filter(|(a, b)| a.contains(b) || b.contains(a))
+        .count()
+}
 
-    println!("{}", c)
+#[aoc(day4, part2)]
+fn part2(pairs: &[Pair]) -> usize {
+    pairs.iter()
+        .filter(|(a, b)| a.overlaps(b))
+        .count()
 }
 
 pub fn main() {
-    part2()
-}
\ No newline at end of file
+    let pairs = generator(include_str!("input.txt")).unwrap();
+    println!("{}", part2(&pairs));
+
+    let elves = elves(&pairs);
+    let coverage = Coverage::new(elves.iter().copied());
+    println!("covered: {}, by exactly one elf: {}", coverage.at_least(1), coverage.exactly(1));
+    println!("uncovered: {:?}", coverage.uncovered().intervals());
+    for (elf, containers) in containing(&elves).iter().enumerate() {
+        println!("{elf} ({}) is contained in {containers:?}", elves[elf]);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use super::*;
+
+    fn interval() -> impl Strategy<Value=Interval<u32>> {
+        (0..60u32, 0..20u32).prop_map(|(start, len)| Interval::new(start, start + len).unwrap())
+    }
+
+    fn values(interval: &Interval<u32>) -> impl Iterator<Item=u32> {
+        interval.start()..=interval.end()
+    }
+
+    #[test]
+    fn test_example() {
+        let pairs = generator(include_str!("test.txt")).unwrap();
+        assert_eq!(part1(&pairs), 2);
+        assert_eq!(part2(&pairs), 4);
+    }
+
+    #[test]
+    fn test_coverage() {
+        let elves = elves(&generator(include_str!("test.txt")).unwrap());
+        let coverage = Coverage::new(elves.iter().copied());
+        assert_eq!(coverage.at_least(1), 8);
+        assert_eq!(coverage.exactly(1), 1);
+        assert_eq!(coverage.at_least(3), 7);
+        assert!(coverage.uncovered().is_empty());
+        assert_eq!(containing(&elves)[3], vec![6, 7, 9, 10, 11]);
+
+        let full = Interval::new(0, u32::MAX).unwrap();
+        let coverage = Coverage::new([full, full]);
+        assert_eq!(coverage.at_least(1), 1 << 32);
+        assert_eq!(coverage.exactly(2), 1 << 32);
+        assert_eq!(coverage.exactly(1), 0);
+
+        let coverage = Coverage::new([Interval::new(1, 2).unwrap(), Interval::new(5, 6).unwrap()]);
+        assert_eq!(coverage.uncovered().intervals(), &[Interval::new(3, 4).unwrap()]);
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert!(generator("2-4,6-8\n2-4;6-8").is_err());
+        assert!(generator("4-2,6-8").is_err());
+        assert!(generator("2-4,6-99999999999").is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn test_contains(a in interval(), b in interval()) {
+            prop_assert_eq!(a.contains(&b), values(&b).all(|x| a.contains_value(x)));
+        }
+
+        #[test]
+        fn test_overlaps(a in interval(), b in interval()) {
+            prop_assert_eq!(a.overlaps(&b), b.overlaps(&a));
+            prop_assert_eq!(a.overlaps(&b), values(&b).any(|x| a.contains_value(x)));
+        }
+
+        #[test]
+        fn test_intersection(a in interval(), b in interval()) {
+            prop_assert_eq!(a.intersection(&b).is_some(), a.overlaps(&b));
+            for x in 0..100 {
+                let in_both = a.contains_value(x) && b.contains_value(x);
+                prop_assert_eq!(a.intersection(&b).is_some_and(|i| i.contains_value(x)), in_both);
+            }
+        }
+
+        #[test]
+        fn test_union(a in interval(), b in interval()) {
+            if let Some(u) = a.union(&b) {
+                let i = a.intersection(&b).unwrap();
+                prop_assert_eq!(u.len(), a.len() + b.len() - i.len());
+                prop_assert!(u.contains(&a) && u.contains(&b));
+            } else {
+                prop_assert!(!a.overlaps(&b));
+            }
+        }
+
+        #[test]
+        fn test_interval_set(intervals in prop::collection::vec(interval(), 0..10)) {
+            let set: IntervalSet<u32> = intervals.iter().copied().collect();
+
+            for pair in set.intervals().windows(2) {
+                prop_assert!(pair[0].end() < pair[1].start());
+            }
+
+            let mut len = 0;
+            for x in 0..100 {
+                let expected = intervals.iter().any(|i| i.contains_value(x));
+                prop_assert_eq!(set.contains_value(x), expected);
+                len += expected as u32;
+            }
+            prop_assert_eq!(set.len(), len);
+
+            for &i in &intervals {
+                let next = Interval::singleton(i.end() + 1);
+                let adjacent: IntervalSet<u32> = [i, next].into_iter().collect();
+                prop_assert_eq!(adjacent.intervals(), &[i, next]);
+            }
+        }
+    }
+}
@@ -1,27 +1,34 @@
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
 use std::mem;
+use std::ops::Range;
+use std::str::FromStr;
+use anyhow::{bail, ensure, Context};
 use pest::iterators::Pair;
 use pest::Parser;
 use pest_derive::Parser;
 
 #[derive(Parser)]
 #[grammar_inline = r#"
-    crat_some = { "[" ~ ASCII_ALPHA ~ "]" }
-    crat_none = { "   " }
-    crat = { crat_some | crat_none }
-    crat_line = { crat ~ (" " ~ crat)* ~ NEWLINE? }
+    label = @{ (!("[" | "]" | WHITE_SPACE) ~ ANY)+ }
+    crat = { "[" ~ label ~ "]" }
+    crat_line = { " "* ~ crat ~ (" "+ ~ crat)* ~ " "* ~ NEWLINE }
 
     number = @{ ASCII_DIGIT+ }
     move_line = {
         "move " ~ number ~ " from " ~ number ~ " to " ~ number ~ NEWLINE?
     }
 
-    crates = {crat_line+}
-    moves = {move_line+}
+    footer = { " "* ~ number ~ (" "+ ~ number)* ~ " "* ~ NEWLINE }
+
+    crates = {crat_line*}
+    moves = {move_line*}
 
     file = {
         crates
-        ~ (" " | ASCII_DIGIT)+ ~ NEWLINE
+        ~ footer
         ~ NEWLINE
         ~ moves
     }
@@ -32,53 +39,256 @@ struct MyParser;
 // *************************************************************************************************
 
 
-#[derive(Debug, Copy, Clone)]
-struct Crate(u8);
+/// A crate, identified by its label (the text between the brackets)
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Crate(String);
 
-struct Move {
-    num: u8,
-    from: u8,
-    to: u8,
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Move {
+    num: usize,
+    from: usize,
+    to: usize,
+    /// The line of the input this move was parsed from, if any
+    line: Option<usize>,
 }
 
-struct Cargo {
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Cargo {
     /// Each stack is a *column*
-    stacks: Vec<Vec<Crate>>,
+    stacks: Vec<Stack>,
+}
+
+/// A stack of crates, stored as a treap keyed by height, so that splitting off, reversing and
+/// appending any number of crates takes logarithmic time
+#[derive(Clone, Default)]
+pub struct Stack {
+    root: Link,
+}
+
+type Link = Option<Box<Node>>;
+
+#[derive(Clone)]
+struct Node {
+    crat: Crate,
+    priority: u64,
+    /// The number of nodes in this subtree
+    size: usize,
+    /// Whether the order of this subtree still has to be reversed
+    reversed: bool,
+    left: Link,
+    right: Link,
+}
+
+/// Iterates over a [Stack], bottom to top
+pub struct StackIter<'a> {
+    /// The nodes still to visit (together with whether they are effectively reversed),
+    /// next one last
+    path: Vec<(&'a Node, bool)>,
+}
+
+/// Whether invalid moves abort execution, or are executed as far as possible
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Mode {
+    Strict,
+    Lenient,
+}
+
+/// Why a move cannot be executed as written
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Problem {
+    /// The (1-based) stack number does not refer to an existing stack
+    NoSuchStack(usize),
+    /// The source stack holds fewer crates than the move wants to take
+    NotEnoughCrates { stack: usize, available: usize },
+}
+
+/// A move which could not be executed (fully) as written
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Warning {
+    pub mov: Move,
+    pub problem: Problem,
+}
+
+/// The order of a group of crates after it has been transferred to another stack
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Order {
+    /// The crates were moved all at once
+    Preserved,
+    /// The crates were moved one at a time, so the top crate ended up at the bottom
+    Reversed,
+}
+
+/// The cost of executing moves
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct Cost {
+    /// The number of times the crane lifted (one or more) crates
+    pub lifts: usize,
+    /// The number of crates that were moved
+    pub crates: usize,
+}
+
+/// A model of crane, which determines how a [Move] rearranges the crates
+pub trait Crane {
+    /// Perform `mov` on `cargo`
+    fn do_move(&mut self, cargo: &mut Cargo, mov: &Move) -> Cost;
+}
+
+/// Moves one crate at a time
+#[derive(Debug, Default, Copy, Clone)]
+pub struct CrateMover9000;
+
+/// Moves any number of crates at once
+#[derive(Debug, Default, Copy, Clone)]
+pub struct CrateMover9001;
+
+/// Moves at most `capacity` crates at once, starting with the top ones
+#[derive(Debug, Copy, Clone)]
+pub struct BoundedCrane {
+    capacity: usize,
+}
+
+/// A cargo together with a list of moves, which is displayed exactly in the puzzle's input format
+#[derive(Debug, Copy, Clone)]
+pub struct Drawing<'a> {
+    pub cargo: &'a Cargo,
+    pub moves: &'a [Move],
+}
+
+/// What a [plan] should achieve
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Target {
+    /// The top crates of all non-empty stacks, as returned by [Cargo::tops]
+    Tops(Vec<Crate>),
+    /// The exact arrangement of all crates
+    Arrangement(Cargo),
+}
+
+/// The result of a [plan]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Plan {
+    /// A shortest sequence of moves that reaches the target
+    Found(Vec<Move>),
+    /// No sequence of moves reaches the target
+    Unreachable,
+    /// The search explored the maximum number of states without reaching the target
+    GaveUp { explored: usize },
+}
+
+/// Undoes a single move, by taking the moved crates off the target stack and putting them back,
+/// in their original order, on the source stack
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Inverse {
+    /// 0-based index of the stack the crates were taken from
+    from: usize,
+    /// 0-based index of the stack the crates were put on
+    to: usize,
+    /// The moved crates, as they were on the source stack before the move (bottom first)
+    crates: Vec<Crate>,
+}
+
+/// A cargo together with the moves applied to it, which can be undone and redone.
+/// Only [History::apply] uses the crane; undoing, redoing and the queries built on them do not,
+/// so e.g. a [Metered] crane is charged once per applied move
+#[derive(Debug, Clone)]
+pub struct History<C> {
+    cargo: Cargo,
+    crane: C,
+    mode: Mode,
+    /// The moves applied so far, with their inverse
+    done: Vec<(Move, Inverse)>,
+    /// The moves that were undone, most recently undone last, with the inverse of their undo.
+    /// Redoing a move replays that, so the crane only sees each move once, when it is applied
+    undone: Vec<(Move, Inverse)>,
+}
+
+/// Wraps another crane and keeps track of the total cost of all moves it performed
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Metered<C> {
+    crane: C,
+    cost: Cost,
 }
 
 
 // *************************************************************************************************
 
 impl Crate {
-    fn as_char(self) -> char {
-        self.0 as char
+    pub fn label(&self) -> &str {
+        &self.0
+    }
+}
+
+/// A label must be non-empty, and cannot contain brackets or whitespace
+impl FromStr for Crate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ensure!(!s.is_empty(), "A crate label cannot be empty");
+        ensure!(
+            !s.contains(|c: char| c == '[' || c == ']' || c.is_whitespace()),
+            "Invalid crate label `{s}`: labels cannot contain brackets or whitespace"
+        );
+        Ok(Self(s.to_string()))
     }
 }
 
 impl Display for Crate {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "[{}]", self.as_char())
+        write!(f, "[{}]", self.0)
     }
 }
 
+/// Writes the crates in the puzzle's format, followed by the footer with stack numbers
+/// (without a trailing newline).
+///
+/// All columns have the same width, which is 3 unless some crate label or stack number is too
+/// long to fit; crates and stack numbers are centered within their column
 impl Display for Cargo {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let highest = self.stacks.iter().map(|c| c.len()).max().unwrap_or(0);
+        let columns: Vec<Vec<_>> = self.stacks.iter().map(|c| c.iter().collect()).collect();
+        let highest = columns.iter().map(|c| c.len()).max().unwrap_or(0);
+        let width =
+            columns.iter()
+                .flatten()
+                .map(|crat| crat.0.len() + 2)
+                .chain([3, self.stacks.len().to_string().len()])
+                .max()
+                .unwrap();
 
         for i in (0..highest).rev() {
-            for c in &self.stacks {
+            for (column, c) in columns.iter().enumerate() {
+                if column > 0 {
+                    write!(f, " ")?
+                }
                 match c.get(i) {
                     Some(crat) => {
-                        write!(f, "{crat} ")?
+                        write!(f, "{:^width$}", crat.to_string())?
                     }
                     None => {
-                        write!(f, "    ")?
+                        write!(f, "{:width$}", "")?
                     }
                 }
             }
             writeln!(f)?;
         }
 
+        for column in 1..=self.stacks.len() {
+            if column > 1 {
+                write!(f, " ")?
+            }
+            write!(f, "{:^width$}", column)?
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes the full puzzle input: the crates, the footer, a blank line and the moves
+impl Display for Drawing<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}\n", self.cargo)?;
+        for mov in self.moves {
+            writeln!(f, "{mov}")?;
+        }
         Ok(())
     }
 }
@@ -91,76 +301,478 @@ impl Display for Move {
 
 // *************************************************************************************************
 
-impl Crate {
-    fn from_ascii(ascii: u8) -> Self {
-        Self(ascii)
+thread_local! {
+    static PRIORITY_SEED: Cell<u64> = const { Cell::new(0x2545_f491_4f6c_dd1d) };
+}
+
+/// A pseudo-random treap priority (splitmix64)
+fn next_priority() -> u64 {
+    PRIORITY_SEED.with(|seed| {
+        let mut z = seed.get().wrapping_add(0x9e37_79b9_7f4a_7c15);
+        seed.set(z);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    })
+}
+
+fn size(link: &Link) -> usize {
+    link.as_ref().map_or(0, |node| node.size)
+}
+
+impl Node {
+    fn new(crat: Crate) -> Box<Self> {
+        Box::new(Self {
+            crat,
+            priority: next_priority(),
+            size: 1,
+            reversed: false,
+            left: None,
+            right: None,
+        })
     }
 
-    /// Call it with the parse result of `crat` rule
-    fn parse(crat: Pair<Rule>) -> Option<Self> {
-        debug_assert_eq!(crat.as_rule(), Rule::crat);
-        let crat = crat.into_inner().next().unwrap();
-        match crat.as_rule() {
-            Rule::crat_some => {
-                Some(Self::from_ascii(
-                    crat.as_str().as_bytes()[1]
-                ))
+    /// Apply a pending reversal to the children of this node
+    fn push_down(&mut self) {
+        if self.reversed {
+            mem::swap(&mut self.left, &mut self.right);
+            for child in [&mut self.left, &mut self.right].into_iter().flatten() {
+                child.reversed ^= true;
             }
-            _ => {
-                None
+            self.reversed = false;
+        }
+    }
+
+    fn update(&mut self) {
+        self.size = 1 + size(&self.left) + size(&self.right);
+    }
+
+    /// The children of this node in order, given whether the node is (effectively) reversed
+    fn children(&self, reversed: bool) -> (&Link, &Link) {
+        if reversed {
+            (&self.right, &self.left)
+        } else {
+            (&self.left, &self.right)
+        }
+    }
+}
+
+/// Concatenate two treaps, all elements of `a` come before those of `b`
+fn merge(a: Link, b: Link) -> Link {
+    match (a, b) {
+        (None, b) => b,
+        (a, None) => a,
+        (Some(mut a), Some(mut b)) => {
+            if a.priority > b.priority {
+                a.push_down();
+                a.right = merge(a.right.take(), Some(b));
+                a.update();
+                Some(a)
+            } else {
+                b.push_down();
+                b.left = merge(Some(a), b.left.take());
+                b.update();
+                Some(b)
             }
         }
     }
 }
 
-impl Cargo {
-    fn push(&mut self, column: usize, crat: Crate) {
-        while self.stacks.len() <= column {
-            self.stacks.push(Vec::new())
+/// Split a treap into its first `at` elements, and the rest
+fn split(link: Link, at: usize) -> (Link, Link) {
+    match link {
+        None => (None, None),
+        Some(mut node) => {
+            node.push_down();
+            let left_size = size(&node.left);
+            if at <= left_size {
+                let (a, b) = split(node.left.take(), at);
+                node.left = b;
+                node.update();
+                (a, Some(node))
+            } else {
+                let (a, b) = split(node.right.take(), at - left_size - 1);
+                node.right = a;
+                node.update();
+                (Some(node), b)
+            }
+        }
+    }
+}
+
+impl Stack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        size(&self.root)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    pub fn push(&mut self, crat: Crate) {
+        self.root = merge(self.root.take(), Some(Node::new(crat)));
+    }
+
+    /// The crate at height `index`, where `0` is the bottom of the stack
+    pub fn get(&self, index: usize) -> Option<Crate> {
+        let mut index = index;
+        let mut link = &self.root;
+        let mut reversed = false;
+        while let Some(node) = link {
+            reversed ^= node.reversed;
+            let (left, right) = node.children(reversed);
+            let left_size = size(left);
+            if index < left_size {
+                link = left;
+            } else if index == left_size {
+                return Some(node.crat.clone());
+            } else {
+                index -= left_size + 1;
+                link = right;
+            }
+        }
+        None
+    }
+
+    /// The top crate
+    pub fn last(&self) -> Option<Crate> {
+        self.get(self.len().checked_sub(1)?)
+    }
+
+    /// Split the stack at height `at`, returning the crates above it (if any)
+    pub fn split_off(&mut self, at: usize) -> Stack {
+        let (bottom, top) = split(self.root.take(), at);
+        self.root = bottom;
+        Stack { root: top }
+    }
+
+    /// Put all crates of `other` on top of this stack, keeping their order
+    pub fn append(&mut self, other: Stack) {
+        self.root = merge(self.root.take(), other.root);
+    }
+
+    /// Turn the stack upside down
+    pub fn reverse(&mut self) {
+        if let Some(root) = &mut self.root {
+            root.reversed ^= true;
+        }
+    }
+
+    /// Iterate over the crates from the bottom to the top of the stack
+    pub fn iter(&self) -> StackIter<'_> {
+        let mut iter = StackIter { path: Vec::new() };
+        iter.descend(&self.root, false);
+        iter
+    }
+}
+
+impl<'a> StackIter<'a> {
+    /// Push the path to the first element of the subtree at `link`
+    fn descend(&mut self, mut link: &'a Link, mut reversed: bool) {
+        while let Some(node) = link {
+            reversed ^= node.reversed;
+            self.path.push((node, reversed));
+            link = node.children(reversed).0;
         }
-        self.stacks[column].push(crat)
     }
+}
+
+impl Iterator for StackIter<'_> {
+    type Item = Crate;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (node, reversed) = self.path.pop()?;
+        self.descend(node.children(reversed).1, reversed);
+        Some(node.crat.clone())
+    }
+}
+
+impl FromIterator<Crate> for Stack {
+    fn from_iter<T: IntoIterator<Item=Crate>>(iter: T) -> Self {
+        let mut stack = Self::new();
+        for crat in iter {
+            stack.push(crat);
+        }
+        stack
+    }
+}
+
+impl PartialEq for Stack {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl Eq for Stack {}
+
+impl Hash for Stack {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.len().hash(state);
+        for crat in self.iter() {
+            crat.hash(state);
+        }
+    }
+}
+
+impl Debug for Stack {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
 
-    fn parse(crates: Pair<Rule>) -> Self {
+impl From<Vec<Vec<Crate>>> for Cargo {
+    fn from(stacks: Vec<Vec<Crate>>) -> Self {
+        Self {
+            stacks: stacks.into_iter().map(Stack::from_iter).collect()
+        }
+    }
+}
+
+
+// *************************************************************************************************
+
+/// The range of (1-based) columns covered by a parsed pair, which is part of the pair `line`
+/// that starts at the beginning of its line.
+/// Unlike `line_col`, this does not scan the input from its start
+fn columns(pair: &Pair<'_, Rule>, line: &Pair<'_, Rule>) -> Range<usize> {
+    let offset = pair.as_span().start() - line.as_span().start();
+    let start = line.as_str()[..offset].chars().count() + 1;
+    start..start + pair.as_str().chars().count()
+}
+
+impl Crate {
+    /// Call it with the parse result of `crat` rule
+    fn parse(crat: Pair<Rule>) -> Self {
+        debug_assert_eq!(crat.as_rule(), Rule::crat);
+        Self(crat.into_inner().next().unwrap().as_str().to_string())
+    }
+}
+
+impl Cargo {
+    /// Call it with the parse result of the `crates` and `footer` rules.
+    ///
+    /// Each crate belongs to the stack whose number in the footer is (partly) below it.
+    /// Fails if the footer does not number the stacks `1, 2, 3, ...`, if a crate is not above
+    /// exactly one stack number, or if a crate is floating above an empty spot
+    fn parse(crates: Pair<Rule>, footer: Pair<Rule>) -> anyhow::Result<Self> {
         debug_assert!(crates.as_rule() == Rule::crates);
+        debug_assert!(footer.as_rule() == Rule::footer);
+
+        // the drawing starts on the first line, and has one line per `crat_line`
+        let crate_lines: Vec<_> = crates.into_inner().collect();
+        let footer_line = crate_lines.len() + 1;
+
+        // stacks may be empty, so only the footer knows how many there are
+        let mut stack_columns = Vec::new();
+        for (i, number) in footer.clone().into_inner().enumerate() {
+            ensure!(
+                number.as_str().parse() == Ok(i + 1),
+                "line {footer_line}: expected stack number {} in the footer, found `{}`",
+                i + 1,
+                number.as_str()
+            );
+            stack_columns.push(columns(&number, &footer));
+        }
 
         let mut cargo = Self {
-            stacks: Vec::with_capacity(16)
+            stacks: vec![Stack::new(); stack_columns.len()]
         };
 
-        for crate_line in crates.into_inner().rev() {
-            crate_line.into_inner()
-                .enumerate()
-                .flat_map(|(i, crat)| -> Option<_> {
-                    Some((i, Crate::parse(crat)?))
-                })
-                .for_each(|(i, crat)| {
-                    cargo.push(i, crat)
-                });
+        for (height, crate_line) in crate_lines.iter().rev().enumerate() {
+            let line = footer_line - 1 - height;
+            for crat in crate_line.clone().into_inner() {
+                let range = columns(&crat, crate_line);
+                let mut below =
+                    stack_columns.iter()
+                        .enumerate()
+                        .filter(|(_, column)| range.start < column.end && column.start < range.end)
+                        .map(|(i, _)| i);
+                let (Some(column), None) = (below.next(), below.next()) else {
+                    bail!(
+                        "line {line}: crate `{}` is not above exactly one of the {} stack numbers",
+                        crat.as_str(),
+                        stack_columns.len()
+                    )
+                };
+
+                let stack = &mut cargo.stacks[column];
+                ensure!(
+                    stack.len() == height,
+                    "line {line}: crate `{}` is floating above stack {}, which holds only {} crate(s)",
+                    crat.as_str(),
+                    column + 1,
+                    stack.len()
+                );
+                stack.push(Crate::parse(crat));
+            }
         }
 
-        cargo
+        Ok(cargo)
     }
 }
 
 impl Move {
-    fn parse(move_line: Pair<Rule>) -> Self {
+    /// Move `num` crates from stack `from` to stack `to`, where stacks are numbered from 1
+    pub fn new(num: usize, from: usize, to: usize) -> Self {
+        Self {
+            num,
+            from,
+            to,
+            line: None,
+        }
+    }
+
+    /// Call it with the parse result of the `move_line` rule, and the line it is on
+    fn parse(move_line: Pair<Rule>, line: usize) -> anyhow::Result<Self> {
         debug_assert_eq!(move_line.as_rule(), Rule::move_line);
         let mut inner = move_line.into_inner();
-        let mut next_num = || -> u8 {
-            inner.next().unwrap().as_str().parse().unwrap()
+        let mut next_num = || -> anyhow::Result<usize> {
+            let number = inner.next().unwrap().as_str();
+            number.parse()
+                .with_context(|| format!("line {line}: invalid number `{number}`"))
         };
 
+        Ok(Self {
+            num: next_num()?,
+            from: next_num()?,
+            to: next_num()?,
+            line: Some(line),
+        })
+    }
+
+    fn parse_moves(moves: Pair<'_, Rule>) -> impl Iterator<Item=anyhow::Result<Self>> + '_ {
+        debug_assert_eq!(moves.as_rule(), Rule::moves);
+        // `line_col` scans the input from its start, so only call it once: move lines are
+        // consecutive
+        let first_line = moves.as_span().start_pos().line_col().0;
+        moves.into_inner()
+            .enumerate()
+            .map(move |(i, move_line)| Move::parse(move_line, first_line + i))
+    }
+}
+
+
+// *************************************************************************************************
+
+impl Cargo {
+    /// Move the top `num` crates of stack `from` onto stack `to` (both 0-based), ending up in
+    /// the given `order`. Moves fewer crates if `from` holds less than `num` crates.
+    /// Returns the number of crates that were moved
+    pub fn transfer(&mut self, from: usize, to: usize, num: usize, order: Order) -> usize {
+        let f = &mut self.stacks[from];
+        let at = f.len().saturating_sub(num);
+        if from == to {
+            // taking crates off a stack and putting them back, one by one or all at once,
+            // leaves the stack as it was
+            return f.len() - at;
+        }
+        let mut crates = f.split_off(at);
+        if order == Order::Reversed {
+            crates.reverse();
+        }
+        let moved = crates.len();
+        self.stacks[to].append(crates);
+        moved
+    }
+
+    /// The top crate of each non-empty stack
+    pub fn tops(&self) -> Vec<Crate> {
+        self.stacks
+            .iter()
+            .flat_map(Stack::last)
+            .collect()
+    }
+
+    /// The labels of the top crates of all non-empty stacks, concatenated
+    pub fn top_crate(&self) -> String {
+        self.tops()
+            .iter()
+            .map(Crate::label)
+            .collect()
+    }
+}
+
+// *************************************************************************************************
+
+impl Move {
+    /// The 0-based indices of the source and target stack
+    fn stacks(&self) -> (usize, usize) {
+        (self.from - 1, self.to - 1)
+    }
+}
+
+impl Crane for CrateMover9000 {
+    fn do_move(&mut self, cargo: &mut Cargo, mov: &Move) -> Cost {
+        let (from, to) = mov.stacks();
+        let crates = cargo.transfer(from, to, mov.num, Order::Reversed);
+        Cost { lifts: crates, crates }
+    }
+}
+
+impl Crane for CrateMover9001 {
+    fn do_move(&mut self, cargo: &mut Cargo, mov: &Move) -> Cost {
+        let (from, to) = mov.stacks();
+        let crates = cargo.transfer(from, to, mov.num, Order::Preserved);
+        Cost { lifts: (crates > 0) as usize, crates }
+    }
+}
+
+impl BoundedCrane {
+    /// Panics if `capacity == 0`
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "A crane must be able to lift at least one crate");
+        Self { capacity }
+    }
+}
+
+impl Crane for BoundedCrane {
+    fn do_move(&mut self, cargo: &mut Cargo, mov: &Move) -> Cost {
+        let (from, to) = mov.stacks();
+        let mut cost = Cost::default();
+        let mut remaining = mov.num;
+        while remaining > 0 {
+            let lifted = cargo.transfer(from, to, remaining.min(self.capacity), Order::Preserved);
+            if lifted == 0 {
+                break;
+            }
+            cost.lifts += 1;
+            cost.crates += lifted;
+            remaining -= lifted;
+        }
+        cost
+    }
+}
+
+impl<C> Metered<C> {
+    pub fn new(crane: C) -> Self {
         Self {
-            num: next_num(),
-            from: next_num(),
-            to: next_num(),
+            crane,
+            cost: Cost::default(),
         }
     }
 
-    fn parse_moves(moves: Pair<Rule>) -> impl Iterator<Item=Self> + '_ {
-        debug_assert_eq!(moves.as_rule(), Rule::moves);
-        moves.into_inner().map(Move::parse)
+    /// The total cost of all moves performed so far
+    pub fn cost(&self) -> Cost {
+        self.cost
+    }
+}
+
+impl<C: Crane + ?Sized> Crane for &mut C {
+    fn do_move(&mut self, cargo: &mut Cargo, mov: &Move) -> Cost {
+        (**self).do_move(cargo, mov)
+    }
+}
+
+impl<C: Crane> Crane for Metered<C> {
+    fn do_move(&mut self, cargo: &mut Cargo, mov: &Move) -> Cost {
+        let cost = self.crane.do_move(cargo, mov);
+        self.cost.lifts += cost.lifts;
+        self.cost.crates += cost.crates;
+        cost
     }
 }
 
@@ -168,88 +780,660 @@ impl Move {
 // *************************************************************************************************
 
 impl Cargo {
-    fn do_move(&mut self, mov: Move) {
-        let num = mov.num as usize;
-        let from = mov.from as usize - 1;
-        let to = mov.to as usize - 1;
+    /// Check whether `mov` can be executed as written
+    pub fn check(&self, mov: &Move) -> Result<(), Problem> {
+        for stack in [mov.from, mov.to] {
+            if stack == 0 || stack > self.stacks.len() {
+                return Err(Problem::NoSuchStack(stack));
+            }
+        }
 
-        self.stacks[to].reserve(num);
-        for _ in 0..num {
-            if let Some(c) = self.stacks[from].pop() {
-                self.stacks[to].push(c)
+        let available = self.stacks[mov.from - 1].len();
+        if available < mov.num {
+            return Err(Problem::NotEnoughCrates { stack: mov.from, available });
+        }
+
+        Ok(())
+    }
+
+    /// Execute `mov` with the given crane.
+    ///
+    /// In [Mode::Strict], an invalid move is an error, and the cargo is left untouched.
+    /// In [Mode::Lenient], a move that refers to a non-existent stack is skipped, and a move that
+    /// wants more crates than are available moves all crates of the stack instead;
+    /// either way, a warning is returned
+    pub fn apply(&mut self, crane: &mut impl Crane, mov: &Move, mode: Mode) -> anyhow::Result<Option<Warning>> {
+        let warning = match self.check(mov) {
+            Ok(()) => None,
+            Err(problem) => {
+                let warning = Warning { mov: *mov, problem };
+                if mode == Mode::Strict {
+                    bail!("{warning}\n{self}")
+                }
+                Some(warning)
             }
+        };
+
+        if !matches!(warning, Some(Warning { problem: Problem::NoSuchStack(_), .. })) {
+            crane.do_move(self, mov);
         }
+        Ok(warning)
     }
+}
 
-    fn do_move_preserve_order(&mut self, mov: Move) {
-        let num = mov.num as usize;
-        let from = mov.from as usize - 1;
-        let to = mov.to as usize - 1;
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::NoSuchStack(stack) => {
+                write!(f, "there is no stack {stack}")
+            }
+            Problem::NotEnoughCrates { stack, available } => {
+                write!(f, "stack {stack} holds only {available} crate(s)")
+            }
+        }
+    }
+}
 
-        let f = &mut self.stacks[from];
-        let len = f.len();
-        let crates = if let Some(at) = len.checked_sub(num) {
-            f.split_off(at)
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(line) = self.mov.line {
+            write!(f, "line {line}: ")?;
+        }
+        write!(f, "`{}`: {}", self.mov, self.problem)
+    }
+}
+
+
+// *************************************************************************************************
+
+impl Cargo {
+    /// Like [Cargo::apply], but also returns the [Inverse] of the move as it was executed
+    pub fn apply_recorded(&mut self, crane: &mut impl Crane, mov: &Move, mode: Mode) -> anyhow::Result<(Option<Warning>, Inverse)> {
+        let (from, to) = (mov.from, mov.to);
+        let in_range = |stack: usize| (1..=self.stacks.len()).contains(&stack);
+        let crates = if in_range(from) && in_range(to) && from != to {
+            let stack = &mut self.stacks[from - 1];
+            let top = stack.split_off(stack.len().saturating_sub(mov.num));
+            let crates = top.iter().collect();
+            stack.append(top);
+            crates
         } else {
-            mem::take(f)
+            // the move is either skipped, or does not change anything
+            Vec::new()
+        };
+
+        let warning = self.apply(crane, mov, mode)?;
+        let inverse = Inverse {
+            from: from.saturating_sub(1),
+            to: to.saturating_sub(1),
+            crates,
         };
-        self.stacks[to].extend_from_slice(&crates);
+        Ok((warning, inverse))
     }
 
-    fn top_crate(self) -> String {
-        self.stacks
-            .iter()
-            .flat_map(|c| -> Option<_> {
-                Some(c.last()?.as_char())
+    /// Undo a move, returning the [Inverse] of the undo, which redoes the move exactly as it was
+    /// executed (without involving a crane)
+    pub fn undo(&mut self, inverse: &Inverse) -> Inverse {
+        let mut redo = Inverse {
+            from: inverse.to,
+            to: inverse.from,
+            crates: Vec::new(),
+        };
+        if inverse.crates.is_empty() {
+            return redo;
+        }
+        let to = &mut self.stacks[inverse.to];
+        let placed = to.split_off(to.len() - inverse.crates.len());
+        redo.crates = placed.iter().collect();
+        self.stacks[inverse.from].append(inverse.crates.iter().cloned().collect());
+        redo
+    }
+
+    /// The 1-based numbers of all stacks that have `crat` on top
+    fn stacks_topped_by<'a>(&'a self, crat: &'a Crate) -> impl Iterator<Item=usize> + 'a {
+        self.stacks.iter()
+            .enumerate()
+            .filter(move |(_, stack)| stack.last().as_ref() == Some(crat))
+            .map(|(i, _)| i + 1)
+    }
+}
+
+impl<C: Crane> History<C> {
+    pub fn new(cargo: Cargo, crane: C, mode: Mode) -> Self {
+        Self {
+            cargo,
+            crane,
+            mode,
+            done: Vec::new(),
+            undone: Vec::new(),
+        }
+    }
+
+    /// The current state of the cargo
+    pub fn cargo(&self) -> &Cargo {
+        &self.cargo
+    }
+
+    pub fn crane(&self) -> &C {
+        &self.crane
+    }
+
+    /// The number of moves applied so far
+    pub fn step(&self) -> usize {
+        self.done.len()
+    }
+
+    /// The total number of moves known to this history, including undone ones
+    pub fn len(&self) -> usize {
+        self.done.len() + self.undone.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Apply a new move, discarding any moves that were undone
+    pub fn apply(&mut self, mov: Move) -> anyhow::Result<Option<Warning>> {
+        self.undone.clear();
+        let (warning, inverse) = self.cargo.apply_recorded(&mut self.crane, &mov, self.mode)?;
+        self.done.push((mov, inverse));
+        Ok(warning)
+    }
+
+    /// Undo the last applied move, returning it, or `None` if no moves were applied
+    pub fn undo(&mut self) -> Option<Move> {
+        let (mov, inverse) = self.done.pop()?;
+        let redo = self.cargo.undo(&inverse);
+        self.undone.push((mov, redo));
+        Some(mov)
+    }
+
+    /// Re-apply the last undone move, returning it, or `None` if there is nothing to redo
+    pub fn redo(&mut self) -> Option<Move> {
+        let (mov, redo) = self.undone.pop()?;
+        let inverse = self.cargo.undo(&redo);
+        self.done.push((mov, inverse));
+        Some(mov)
+    }
+
+    /// Undo or redo moves until exactly `step` moves are applied.
+    /// Fails if `step` is larger than the number of known moves
+    pub fn seek(&mut self, step: usize) -> anyhow::Result<&Cargo> {
+        ensure!(step <= self.len(), "Cannot go to step {step}, there are only {} moves", self.len());
+        while self.step() > step {
+            self.undo();
+        }
+        while self.step() < step {
+            self.redo();
+        }
+        Ok(&self.cargo)
+    }
+
+    /// The state of the cargo after the first `n` moves
+    pub fn state_after(&mut self, n: usize) -> anyhow::Result<Cargo> {
+        let step = self.step();
+        let cargo = self.seek(n)?.clone();
+        self.seek(step)?;
+        Ok(cargo)
+    }
+
+    /// The first step (`0` being the initial state) at which a crate with the given label was on
+    /// top of a stack, together with that stack's number
+    pub fn first_on_top(&mut self, crat: &Crate) -> Option<(usize, usize)> {
+        let step = self.step();
+        self.seek(0).unwrap();
+
+        let mut found = self.cargo.stacks_topped_by(crat).next().map(|stack| (0, stack));
+        while found.is_none() && self.redo().is_some() {
+            found = self.cargo.stacks_topped_by(crat).next().map(|stack| (self.step(), stack));
+        }
+
+        self.seek(step).unwrap();
+        found
+    }
+
+    /// The applied moves, in order
+    pub fn moves(&self) -> impl Iterator<Item=&Move> {
+        self.done.iter().map(|(mov, _)| mov)
+    }
+}
+
+
+// *************************************************************************************************
+
+impl Cargo {
+    /// The number of crates with each label, which no move can change
+    fn label_counts(&self) -> HashMap<Crate, usize> {
+        let mut counts = HashMap::new();
+        for crat in self.stacks.iter().flat_map(Stack::iter) {
+            *counts.entry(crat).or_default() += 1;
+        }
+        counts
+    }
+
+    /// All moves that take at least one crate from an existing stack to another one
+    fn possible_moves(&self) -> impl Iterator<Item=Move> + '_ {
+        let n = self.stacks.len();
+        (0..n)
+            .flat_map(move |from| (0..n).map(move |to| (from, to)))
+            .filter(|(from, to)| from != to)
+            .flat_map(move |(from, to)| {
+                (1..=self.stacks[from].len()).map(move |num| Move::new(num, from + 1, to + 1))
             })
-            .collect()
     }
 }
 
+impl Target {
+    fn is_reached(&self, cargo: &Cargo) -> bool {
+        match self {
+            Target::Tops(tops) => cargo.tops() == *tops,
+            Target::Arrangement(target) => cargo == target,
+        }
+    }
+
+    /// A quick check whether the target can be reached from `start` at all.
+    /// If this returns `true`, the target might still be unreachable
+    fn is_possible(&self, start: &Cargo) -> bool {
+        let available = start.label_counts();
+        match self {
+            Target::Tops(tops) => {
+                let wanted: Cargo = tops.iter().map(|crat| vec![crat.clone()]).collect::<Vec<_>>().into();
+                tops.len() <= start.stacks.len()
+                    && wanted.label_counts().iter()
+                    .all(|(crat, count)| available.get(crat).is_some_and(|a| a >= count))
+            }
+            Target::Arrangement(target) => {
+                target.stacks.len() == start.stacks.len()
+                    && target.label_counts() == available
+            }
+        }
+    }
+}
+
+/// Search for a shortest sequence of moves that turns `start` into `target` with the given crane,
+/// exploring at most `max_states` distinct arrangements
+pub fn plan(start: &Cargo, target: &Target, crane: &mut impl Crane, max_states: usize) -> Plan {
+    if !target.is_possible(start) {
+        return Plan::Unreachable;
+    }
+
+    // breadth-first search, where each node remembers the node and move it was reached from
+    let mut nodes: Vec<(Cargo, Option<(usize, Move)>)> = vec![(start.clone(), None)];
+    let mut seen = HashSet::from([start.clone()]);
+    let mut next = 0;
+
+    while let Some((cargo, _)) = nodes.get(next) {
+        if target.is_reached(cargo) {
+            let mut moves = Vec::new();
+            let mut node = next;
+            while let Some((parent, mov)) = nodes[node].1 {
+                moves.push(mov);
+                node = parent;
+            }
+            moves.reverse();
+            return Plan::Found(moves);
+        }
+
+        let cargo = cargo.clone();
+        for mov in cargo.possible_moves() {
+            let mut after = cargo.clone();
+            crane.do_move(&mut after, &mov);
+            if seen.insert(after.clone()) {
+                if seen.len() > max_states {
+                    return Plan::GaveUp { explored: max_states };
+                }
+                nodes.push((after, Some((next, mov))));
+            }
+        }
+        next += 1;
+    }
+
+    Plan::Unreachable
+}
+
+
 // *************************************************************************************************
 
-fn parse(input: &str) -> (Cargo, impl Iterator<Item=Move> + '_) {
-    let mut parse = MyParser::parse(Rule::file, input)
-        .expect("Parse Error")
+fn parse(input: &str) -> anyhow::Result<(Cargo, impl Iterator<Item=anyhow::Result<Move>> + '_)> {
+    let mut parse = MyParser::parse(Rule::file, input)?
         .next().unwrap()
         .into_inner(); // get and unwrap the `file` rule; never fails;
 
-    (
-        Cargo::parse(parse.next().unwrap()),
+    Ok((
+        Cargo::parse(parse.next().unwrap(), parse.next().unwrap())?,
         Move::parse_moves(parse.next().unwrap())
-    )
+    ))
 }
 
-#[aoc(day5, part1)]
-fn part1(input: &str) -> String {
-    let (mut cargo, moves) = parse(input);
-    println!("{cargo}");
+/// Parse a whole drawing, the inverse of writing a [Drawing]
+pub fn parse_drawing(input: &str) -> anyhow::Result<(Cargo, Vec<Move>)> {
+    let (cargo, moves) = parse(input)?;
+    Ok((cargo, moves.collect::<anyhow::Result<_>>()?))
+}
 
+/// Execute all moves of the input with the given crane, returning the top crate of each stack
+/// and the warnings for all moves that could not be executed as written
+pub fn run(input: &str, crane: &mut impl Crane, mode: Mode) -> anyhow::Result<(String, Vec<Warning>)> {
+    let (mut cargo, moves) = parse(input)?;
+
+    let mut warnings = Vec::new();
     for mov in moves {
-        println!("{mov}");
-        cargo.do_move(mov);
-        println!("{cargo}")
+        let mov = mov?;
+        if let Some(warning) = cargo.apply(crane, &mov, mode)? {
+            println!("warning: {warning}");
+            warnings.push(warning);
+        }
     }
+    println!("{cargo}");
 
-    cargo.top_crate()
+    Ok((cargo.top_crate(), warnings))
 }
 
-#[aoc(day5, part2)]
-fn part2(input: &str) -> String {
-    let (mut cargo, moves) = parse(input);
-    println!("{cargo}");
-
-    for mov in moves {
-        println!("{mov}");
-        cargo.do_move_preserve_order(mov);
-        println!("{cargo}")
-    }
+#[aoc(day5, part1)]
+fn part1(input: &str) -> anyhow::Result<String> {
+    Ok(run(input, &mut CrateMover9000, Mode::Strict)?.0)
+}
 
-    cargo.top_crate()
+#[aoc(day5, part2)]
+fn part2(input: &str) -> anyhow::Result<String> {
+    Ok(run(input, &mut CrateMover9001, Mode::Strict)?.0)
 }
 
 pub fn main() {
     println!("{}",
-             part2(include_str!("./test.txt"))
+             part2(include_str!("./test.txt")).unwrap()
     );
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use super::*;
+
+    fn crat() -> impl Strategy<Value=Crate> {
+        "[A-Za-z0-9]{1,4}".prop_map(Crate)
+    }
+
+    fn cargo() -> impl Strategy<Value=Cargo> {
+        let stack = prop::collection::vec(crat(), 0..8);
+        prop::collection::vec(stack, 1..15)
+            .prop_map(Cargo::from)
+    }
+
+    fn moves() -> impl Strategy<Value=Vec<Move>> {
+        let mov = (1..=1000usize, 1..=15usize, 1..=15usize)
+            .prop_map(|(num, from, to)| Move::new(num, from, to));
+        prop::collection::vec(mov, 0..10)
+    }
+
+    /// The original implementation, which moves crates between plain vectors
+    mod reference {
+        use super::*;
+
+        pub fn do_move(stacks: &mut [Vec<Crate>], mov: &Move) {
+            let (from, to) = mov.stacks();
+            for _ in 0..mov.num {
+                if let Some(crat) = stacks[from].pop() {
+                    stacks[to].push(crat);
+                }
+            }
+        }
+
+        pub fn do_move_preserve_order(stacks: &mut [Vec<Crate>], mov: &Move) {
+            let (from, to) = mov.stacks();
+            let f = &mut stacks[from];
+            let mut crates = f.split_off(f.len().saturating_sub(mov.num));
+            stacks[to].append(&mut crates);
+        }
+    }
+
+    fn stacks_and_moves() -> impl Strategy<Value=(Vec<Vec<Crate>>, Vec<Move>)> {
+        let stack = prop::collection::vec(crat(), 0..30);
+        prop::collection::vec(stack, 1..10)
+            .prop_flat_map(|stacks| {
+                let n = stacks.len();
+                let mov = (1..=40usize, 1..=n, 1..=n)
+                    .prop_map(|(num, from, to)| Move::new(num, from, to));
+                (Just(stacks), prop::collection::vec(mov, 0..50))
+            })
+    }
+
+    /// Checks that seeking in a history of `moves` reproduces the cargo after each step
+    fn check_history(mut crane: impl Crane + Copy, stacks: &[Vec<Crate>], moves: &[Move]) -> Result<(), TestCaseError> {
+        let start = Cargo::from(stacks.to_vec());
+        let mut states = vec![start.clone()];
+        let mut history = History::new(start.clone(), crane, Mode::Lenient);
+        for mov in moves {
+            history.apply(*mov).unwrap();
+            let mut cargo = states.last().unwrap().clone();
+            crane.do_move(&mut cargo, mov);
+            states.push(cargo);
+        }
+
+        prop_assert_eq!(history.seek(0).unwrap(), &start);
+        prop_assert_eq!(history.seek(moves.len()).unwrap(), states.last().unwrap());
+        prop_assert_eq!(history.seek(0).unwrap(), &start);
+        for (step, state) in states.iter().enumerate() {
+            prop_assert_eq!(&history.state_after(step).unwrap(), state);
+        }
+        prop_assert!(history.seek(moves.len() + 1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_input() {
+        let input = include_str!("../../input/2022/day5.txt");
+        let (cargo, moves) = parse_drawing(input).unwrap();
+        assert_eq!(Drawing { cargo: &cargo, moves: &moves }.to_string(), input);
+    }
+
+    #[test]
+    fn test_wide_drawing() {
+        let input = concat!(
+            "                                         [XY]\n",
+            "[A]                                      [Z] [LONG]\n",
+            " 1   2   3   4   5   6   7   8   9   10   11   12\n",
+            "\n",
+            "move 300 from 12 to 1\n",
+        );
+        let (cargo, moves) = parse_drawing(input).unwrap();
+        assert_eq!(cargo.stacks.len(), 12);
+        assert_eq!(cargo.stacks[10].iter().map(|c| c.0).collect::<Vec<_>>(), ["Z", "XY"]);
+        assert_eq!(cargo.top_crate(), "AXYLONG");
+        assert_eq!(moves, [Move { num: 300, from: 12, to: 1, line: Some(5) }]);
+    }
+
+    #[test]
+    fn test_example() {
+        assert_eq!(part1(include_str!("test.txt")).unwrap(), "CMZ");
+        assert_eq!(part2(include_str!("test.txt")).unwrap(), "MCD");
+    }
+
+    #[test]
+    fn test_cranes() {
+        let mut bounded = Metered::new(BoundedCrane::new(2));
+        let (tops, warnings) = run(include_str!("test.txt"), &mut bounded, Mode::Strict).unwrap();
+        assert_eq!(tops, "MCZ");
+        assert!(warnings.is_empty());
+        assert_eq!(bounded.cost(), Cost { lifts: 5, crates: 7 });
+
+        let mut metered = Metered::new(CrateMover9000);
+        run(include_str!("test.txt"), &mut metered, Mode::Strict).unwrap();
+        assert_eq!(metered.cost(), Cost { lifts: 7, crates: 7 });
+
+        let mut metered = Metered::new(CrateMover9001);
+        run(include_str!("test.txt"), &mut metered, Mode::Strict).unwrap();
+        assert_eq!(metered.cost(), Cost { lifts: 4, crates: 7 });
+
+        // one lift per non-empty move, however many crates it takes
+        let mut cargo = Cargo::from(vec![vec![Crate("A".into()), Crate("B".into())], vec![]]);
+        assert_eq!(CrateMover9001.do_move(&mut cargo, &Move::new(5, 1, 2)), Cost { lifts: 1, crates: 2 });
+        assert_eq!(CrateMover9001.do_move(&mut cargo, &Move::new(1, 1, 2)), Cost { lifts: 0, crates: 0 });
+        assert_eq!(CrateMover9001.do_move(&mut cargo, &Move::new(2, 2, 2)), Cost { lifts: 1, crates: 2 });
+        assert_eq!(cargo.top_crate(), "B");
+    }
+
+    #[test]
+    fn test_modes() {
+        let example = include_str!("test.txt");
+        let header = &example[..example.find("move").unwrap()];
+        let at_line = |num, from, to, line| Move { num, from, to, line: Some(line) };
+
+        for bad in ["move 1 from 0 to 1", "move 1 from 1 to 4", "move 4 from 2 to 1"] {
+            let error = run(&format!("{header}move 1 from 2 to 1\n{bad}\n"), &mut CrateMover9000, Mode::Strict)
+                .unwrap_err()
+                .to_string();
+            assert!(error.starts_with(&format!("line 7: `{bad}`")), "{error}");
+        }
+
+        let input = format!("{header}move 1 from 0 to 1\nmove 1 from 1 to 4\nmove 5 from 2 to 1\n");
+        let expected = [
+            Warning { mov: at_line(1, 0, 1, 6), problem: Problem::NoSuchStack(0) },
+            Warning { mov: at_line(1, 1, 4, 7), problem: Problem::NoSuchStack(4) },
+            Warning { mov: at_line(5, 2, 1, 8), problem: Problem::NotEnoughCrates { stack: 2, available: 3 } },
+        ];
+        let (tops, warnings) = run(&input, &mut CrateMover9000, Mode::Lenient).unwrap();
+        assert_eq!(warnings, expected);
+        // the first two moves are skipped, the last one moves all three crates of stack 2
+        assert_eq!(tops, "MP");
+        assert_eq!(run(&input, &mut CrateMover9001, Mode::Lenient).unwrap(), ("DP".to_owned(), expected.to_vec()));
+    }
+
+    #[test]
+    fn test_first_on_top() {
+        let (cargo, moves) = parse_drawing(include_str!("test.txt")).unwrap();
+        let mut history = History::new(cargo, CrateMover9000, Mode::Strict);
+        for mov in moves {
+            history.apply(mov).unwrap();
+        }
+
+        let first_on_top = |history: &mut History<_>, label: &str| history.first_on_top(&Crate(label.to_owned()));
+        assert_eq!(first_on_top(&mut history, "D"), Some((0, 2)));
+        assert_eq!(first_on_top(&mut history, "C"), Some((1, 2)));
+        assert_eq!(first_on_top(&mut history, "Z"), Some((2, 3)));
+        assert_eq!(first_on_top(&mut history, "M"), Some((3, 1)));
+        assert_eq!(first_on_top(&mut history, "X"), None);
+        // searching does not change the current step
+        assert_eq!(history.step(), 4);
+        assert_eq!(history.cargo().top_crate(), "CMZ");
+    }
+
+    #[test]
+    fn test_history_cost() {
+        let (cargo, moves) = parse_drawing(include_str!("test.txt")).unwrap();
+        let mut history = History::new(cargo, Metered::new(CrateMover9000), Mode::Strict);
+        for mov in moves {
+            history.apply(mov).unwrap();
+        }
+        let cost = Cost { lifts: 7, crates: 7 };
+        assert_eq!(history.crane().cost(), cost);
+
+        // time travel replays the recorded moves, without using the crane again
+        history.state_after(1).unwrap();
+        history.first_on_top(&Crate("M".to_owned()));
+        history.seek(0).unwrap();
+        history.seek(4).unwrap();
+        assert_eq!(history.cargo().top_crate(), "CMZ");
+        assert_eq!(history.crane().cost(), cost);
+
+        history.seek(2).unwrap();
+        history.apply(Move::new(1, 3, 1)).unwrap();
+        assert_eq!(history.crane().cost(), Cost { lifts: 8, crates: 8 });
+        assert_eq!(history.len(), 3);
+    }
+
+    #[test]
+    fn test_plan() {
+        let cargo = |stacks: &[&[&str]]| -> Cargo {
+            stacks.iter()
+                .map(|stack| stack.iter().map(|label| Crate(label.to_string())).collect())
+                .collect::<Vec<_>>()
+                .into()
+        };
+        let start = cargo(&[&["A", "B"], &["C"], &[]]);
+        let reversed = Target::Arrangement(cargo(&[&[], &["C", "B", "A"], &[]]));
+        let tops = Target::Tops(vec![Crate("A".into()), Crate("C".into()), Crate("B".into())]);
+
+        fn replay(start: &Cargo, target: &Target, mut crane: impl Crane, expected_len: usize) {
+            let Plan::Found(moves) = plan(start, target, &mut crane, 1000) else {
+                panic!("no plan found for {target:?}")
+            };
+            assert_eq!(moves.len(), expected_len, "{moves:?}");
+            let mut cargo = start.clone();
+            for mov in &moves {
+                cargo.apply(&mut crane, mov, Mode::Strict).unwrap();
+            }
+            assert!(target.is_reached(&cargo), "{moves:?} lead to {cargo:?}");
+        }
+        replay(&start, &reversed, CrateMover9000, 1);
+        replay(&start, &reversed, CrateMover9001, 2);
+        replay(&start, &tops, CrateMover9000, 1);
+        replay(&start, &tops, CrateMover9001, 1);
+        replay(&start, &Target::Arrangement(start.clone()), CrateMover9001, 0);
+
+        for target in [
+            Target::Arrangement(cargo(&[&["A", "B", "C", "D"], &[], &[]])),
+            Target::Arrangement(cargo(&[&["A", "B", "B"], &[], &[]])),
+            Target::Arrangement(cargo(&[&["A", "B", "C"], &[]])),
+            Target::Tops(vec![Crate("D".into())]),
+            Target::Tops(vec![Crate("C".into()), Crate("C".into())]),
+        ] {
+            assert_eq!(plan(&start, &target, &mut CrateMover9000, 1000), Plan::Unreachable, "{target:?}");
+        }
+
+        assert_eq!(plan(&start, &reversed, &mut CrateMover9001, 2), Plan::GaveUp { explored: 2 });
+    }
+
+    #[test]
+    fn test_bad_footer() {
+        // the last crate is not above any stack number
+        let error = |input| parse_drawing(input).unwrap_err().to_string();
+        assert!(error("[A]\n[A] [B] [C]\n 1   2\n\n").starts_with("line 2: crate `[C]`"));
+        // stack 3 is missing
+        assert!(error("[A] [B]\n 1   2   4\n\n").starts_with("line 2: expected stack number 3"));
+        // crate `B` floats above an empty spot
+        assert!(error("    [B]\n[A]\n 1   2\n\n").starts_with("line 1: crate `[B]` is floating"));
+        // columns are counted in characters, not bytes
+        assert_eq!(parse_drawing("[ÄÄ] [B]\n 1   2\n\n").unwrap().0.top_crate(), "ÄÄB");
+    }
+
+    proptest! {
+        #[test]
+        fn test_round_trip(cargo in cargo(), moves in moves()) {
+            let written = Drawing { cargo: &cargo, moves: &moves }.to_string();
+            let (parsed_cargo, parsed_moves) = parse_drawing(&written).unwrap();
+
+            prop_assert_eq!(parsed_cargo, cargo);
+            // parsed moves remember their line, which is irrelevant here
+            let parsed_moves: Vec<_> =
+                parsed_moves.iter()
+                    .map(|mov| Move::new(mov.num, mov.from, mov.to))
+                    .collect();
+            prop_assert_eq!(parsed_moves, moves);
+        }
+
+        #[test]
+        fn test_against_reference((stacks, moves) in stacks_and_moves()) {
+            let mut old = stacks.clone();
+            let mut old_preserved = stacks.clone();
+            let mut new = Cargo::from(stacks.clone());
+            let mut new_preserved = Cargo::from(stacks);
+
+            for mov in &moves {
+                reference::do_move(&mut old, mov);
+                reference::do_move_preserve_order(&mut old_preserved, mov);
+                CrateMover9000.do_move(&mut new, mov);
+                CrateMover9001.do_move(&mut new_preserved, mov);
+
+                prop_assert_eq!(&new, &Cargo::from(old.clone()));
+                prop_assert_eq!(&new_preserved, &Cargo::from(old_preserved.clone()));
+            }
+        }
+
+        #[test]
+        fn test_history((stacks, moves) in stacks_and_moves()) {
+            check_history(CrateMover9000, &stacks, &moves)?;
+            check_history(CrateMover9001, &stacks, &moves)?;
+        }
+    }
+}
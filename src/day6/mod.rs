@@ -1,33 +1,124 @@
-use std::array;
-use std::fmt::{Display, Formatter};
-use std::ops::BitOr;
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read};
+use anyhow::Context;
 
 
 // *************************************************************************************************
 
 
-struct Window<const N: usize> (
-    [u8; N]
-);
+/// The distinct symbols that occur in an input, each identified by a dense index
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Alphabet<T> {
+    /// Sorted, without duplicates; the index of a symbol is its position in this list
+    symbols: Vec<T>,
+}
+
+/// Keeps track of which symbols occur in a window that slides over the input, one symbol at a
+/// time
+struct Window {
+    /// How often each symbol occurs in the window, indexed by its index in the [Alphabet]
+    counts: Vec<usize>,
+    /// The number of characters in the window that are a repeat of an earlier one,
+    /// so the window is unique iff this is zero
+    repeats: usize,
+}
+
+/// Finds all markers of a given size in a stream of bytes, one byte at a time.
+/// Only the last `size` bytes are kept, in a ring buffer
+pub struct Detector {
+    size: usize,
+    /// The last `size` bytes; the byte at offset `i` is stored at index `i % size`
+    ring: Vec<u8>,
+    window: Window,
+    /// The number of bytes seen so far
+    offset: usize,
+}
+
+/// The end of a run of `size` consecutive, all different bytes
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Marker {
+    pub size: usize,
+    /// The number of bytes up to and including the marker
+    pub offset: usize,
+}
+
+/// Iterates over all markers of some sizes in a byte stream, as soon as they are read, ordered by
+/// offset (and by size for markers at the same offset)
+pub struct Markers<R> {
+    reader: R,
+    detectors: Vec<Detector>,
+    /// Bytes that were read, but not yet fed to the detectors
+    buffer: Vec<u8>,
+    start: usize,
+    end: usize,
+    /// Markers that were found, but not yet returned
+    found: VecDeque<Marker>,
+    done: bool,
+}
 
+/// A part of a datastream that starts right after a start-of-message marker, and ends right
+/// before the next one (or at the end of the stream)
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Frame<'a> {
+    /// The offset of the first byte of the payload in the stream
+    pub offset: usize,
+    pub payload: &'a [u8],
+    /// The part of the payload before the first start-of-packet marker
+    pub preamble: &'a [u8],
+    pub packets: Vec<Packet<'a>>,
+}
+
+/// A part of a frame's payload that starts right after a start-of-packet marker, and ends right
+/// before the next one (or at the end of the frame)
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Packet<'a> {
+    /// The offset of the first byte of the payload in the stream
+    pub offset: usize,
+    pub payload: &'a [u8],
+}
 
-struct WindowIter<'a, const N: usize> (
-    &'a str
-);
+/// Decodes a datastream into [Frame]s.
+///
+/// Markers never overlap: after a marker is found, the search for the next one starts afresh
+/// with the byte after it. Bytes before the first start-of-message marker are skipped
+pub struct Frames<'a> {
+    data: &'a [u8],
+    message: Detector,
+    packet_size: usize,
+    /// The offset of the next byte to feed to the message detector
+    pos: usize,
+    /// The offset of the payload of the frame that is currently being read, if any
+    start: Option<usize>,
+}
 
 
 // *************************************************************************************************
 
 
-impl<'a, const N: usize> Iterator for WindowIter<'a, N> {
-    type Item = Window<N>;
+impl<T: Ord + Copy> Alphabet<T> {
+    /// The alphabet of all symbols in `input`
+    pub fn of(input: impl IntoIterator<Item=T>) -> Self {
+        let mut symbols: Vec<_> = input.into_iter().collect();
+        symbols.sort_unstable();
+        symbols.dedup();
+        Self { symbols }
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let res = self.0.as_bytes().get(0..N)?;
-        self.0 = &self.0[1..];
-        Some(Window(
-            array::from_fn(|i| res[i])
-        ))
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn symbols(&self) -> &[T] {
+        &self.symbols
+    }
+
+    /// The index of `x`, or `None` if `x` is not part of the alphabet
+    pub fn index(&self, x: T) -> Option<usize> {
+        self.symbols.binary_search(&x).ok()
     }
 }
 
@@ -35,59 +126,322 @@ impl<'a, const N: usize> Iterator for WindowIter<'a, N> {
 // *************************************************************************************************
 
 
-impl<const N: usize> Window<N> {
-    fn mask(x: &u8) -> u32 {
-        1 << (x - b'a')
+impl Window {
+    /// An empty window over an alphabet with `len` symbols
+    fn new(len: usize) -> Self {
+        Self {
+            counts: vec![0; len],
+            repeats: 0,
+        }
+    }
+
+    fn add(&mut self, x: usize) {
+        let count = &mut self.counts[x];
+        if *count > 0 {
+            self.repeats += 1;
+        }
+        *count += 1;
+    }
+
+    fn remove(&mut self, x: usize) {
+        let count = &mut self.counts[x];
+        *count -= 1;
+        if *count > 0 {
+            self.repeats -= 1;
+        }
     }
 
     fn is_unique(&self) -> bool {
-        let ones =
-            self.0
-                .iter()
-                .map(Self::mask)
-                .fold(0, u32::bitor)
-                .count_ones();
-        ones as usize == N
+        self.repeats == 0
     }
 }
 
 
 // *************************************************************************************************
 
-impl<const N: usize> Display for Window<N> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        for c in self.0 {
-            write!(f, "{}", c as char)?;
+impl Detector {
+    /// Panics if `size == 0`
+    pub fn new(size: usize) -> Self {
+        assert!(size > 0, "A marker must be at least one byte long");
+        Self {
+            size,
+            ring: vec![0; size],
+            window: Window::new(256),
+            offset: 0,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The number of bytes seen so far
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Forget all bytes seen so far
+    pub fn reset(&mut self) {
+        for &x in &self.ring[..self.offset.min(self.size)] {
+            self.window.remove(x as usize);
         }
-        Ok(())
+        self.offset = 0;
+    }
+
+    /// Feed the next byte of the stream, returning a marker if it ends one
+    pub fn push(&mut self, x: u8) -> Option<Marker> {
+        let slot = &mut self.ring[self.offset % self.size];
+        if self.offset >= self.size {
+            self.window.remove(*slot as usize);
+        }
+        *slot = x;
+        self.window.add(x as usize);
+        self.offset += 1;
+
+        (self.offset >= self.size && self.window.is_unique())
+            .then_some(Marker { size: self.size, offset: self.offset })
     }
 }
 
-// *************************************************************************************************
+impl<R: Read> Markers<R> {
+    /// Look for markers of each of the given `sizes`, which must all be positive
+    pub fn new(reader: R, sizes: &[usize]) -> Self {
+        Self {
+            reader,
+            detectors: sizes.iter().map(|&size| Detector::new(size)).collect(),
+            buffer: vec![0; 64 * 1024],
+            start: 0,
+            end: 0,
+            found: VecDeque::new(),
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> Iterator for Markers<R> {
+    type Item = std::io::Result<Marker>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(marker) = self.found.pop_front() {
+                return Some(Ok(marker));
+            }
+            if self.done {
+                return None;
+            }
+
+            if self.start == self.end {
+                match self.reader.read(&mut self.buffer) {
+                    Ok(0) => {
+                        self.done = true;
+                    }
+                    Ok(n) => {
+                        self.start = 0;
+                        self.end = n;
+                    }
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => {
+                        self.done = true;
+                        return Some(Err(e));
+                    }
+                }
+                continue;
+            }
 
-fn first_marker<const N: usize>(input: &str) -> usize {
-    WindowIter::<N>(input)
-        .enumerate()
-        .inspect(|(_, w)| eprintln!("{w}"))
-        .find(|(_, w)| w.is_unique())
-        .unwrap()
-        .0 + N
+            // feed bytes until at least one marker is found, or the buffer is exhausted
+            while self.start < self.end && self.found.is_empty() {
+                let x = self.buffer[self.start];
+                self.start += 1;
+                self.found.extend(self.detectors.iter_mut().flat_map(|d| d.push(x)));
+            }
+        }
+    }
+}
+
+/// Decode `data` into frames, delimited by start-of-message markers of `message_size` bytes,
+/// each of which is split into packets by start-of-packet markers of `packet_size` bytes
+pub fn decode(data: &[u8], packet_size: usize, message_size: usize) -> Frames<'_> {
+    Frames {
+        data,
+        message: Detector::new(message_size),
+        packet_size,
+        pos: 0,
+        start: None,
+    }
+}
+
+impl<'a> Frames<'a> {
+    /// The frame with payload `data[start..end]`
+    fn frame(&self, start: usize, end: usize) -> Frame<'a> {
+        let payload = &self.data[start..end];
+        let mut detector = Detector::new(self.packet_size);
+
+        // the payload start of each packet
+        let mut starts = Vec::new();
+        for (i, &x) in payload.iter().enumerate() {
+            if detector.push(x).is_some() {
+                starts.push(i + 1);
+                detector.reset();
+            }
+        }
+
+        // each packet ends where the marker of the next one begins
+        let ends = starts.iter().skip(1).map(|&next| next - self.packet_size).chain([payload.len()]);
+        let packets =
+            starts.iter()
+                .zip(ends)
+                .map(|(&s, e)| Packet { offset: start + s, payload: &payload[s..e] })
+                .collect();
+        let preamble = &payload[..starts.first().map_or(payload.len(), |&s| s - self.packet_size)];
+
+        Frame { offset: start, payload, preamble, packets }
+    }
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = Frame<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(&x) = self.data.get(self.pos) {
+            self.pos += 1;
+            if self.message.push(x).is_some() {
+                self.message.reset();
+                if let Some(start) = self.start.replace(self.pos) {
+                    return Some(self.frame(start, self.pos - self.message.size()));
+                }
+            }
+        }
+
+        let start = self.start.take()?;
+        Some(self.frame(start, self.data.len()))
+    }
+}
+
+/// The number of symbols up to and including the first `size` consecutive, all different ones,
+/// where `index` maps each symbol into `0..len`
+fn first_distinct_run<T: Copy>(input: &[T], size: usize, len: usize, index: impl Fn(T) -> usize) -> Option<usize> {
+    if size == 0 {
+        return Some(0);
+    }
+
+    let mut window = Window::new(len);
+    for (i, &x) in input.iter().enumerate() {
+        window.add(index(x));
+        if i >= size {
+            window.remove(index(input[i - size]));
+        }
+        if i + 1 >= size && window.is_unique() {
+            return Some(i + 1);
+        }
+    }
+    None
+}
+
+/// The number of symbols up to and including the first marker, i.e., the first `size`
+/// consecutive symbols that are all different. Returns `None` if there is no marker.
+///
+/// The symbols can be of any (ordered) type; only those that actually occur in `input` are
+/// tracked. This builds the [Alphabet] of `input` first, and then makes a second pass over it;
+/// use [first_marker] for bytes, which needs neither
+pub fn first_marker_in<T: Ord + Copy>(input: &[T], size: usize) -> Option<usize> {
+    let alphabet = Alphabet::of(input.iter().copied());
+    first_distinct_run(input, size, alphabet.len(), |x| alphabet.index(x).unwrap())
+}
+
+/// Like [first_marker_in], where each byte of `input` is a symbol.
+/// Runs in a single pass over `input`, regardless of `size`
+pub fn first_marker(input: &str, size: usize) -> Option<usize> {
+    first_distinct_run(input.as_bytes(), size, 256, |x| x as usize)
+}
+
+/// Like [first_marker_in], where each Unicode scalar value (`char`) of `input` is a symbol.
+/// The result counts `char`s, not bytes
+pub fn first_marker_chars(input: &str, size: usize) -> Option<usize> {
+    first_marker_in(&input.chars().collect::<Vec<_>>(), size)
 }
 
 #[aoc(day6, part1)]
-pub fn part1(input: &str) -> usize {
-    first_marker::<4>(input)
+pub fn part1(input: &str) -> anyhow::Result<usize> {
+    first_marker(input.trim(), 4).context("There is no start-of-packet marker")
 }
 
 #[aoc(day6, part2)]
-pub fn part2(input: &str) -> usize {
-    first_marker::<14>(input)
+pub fn part2(input: &str) -> anyhow::Result<usize> {
+    first_marker(input.trim(), 14).context("There is no start-of-message marker")
 }
 
 
 pub fn main() {
     include_str!("test.txt").lines()
         .for_each(|line|
-            println!("{line}:\t{}", part2(line.trim()))
+            println!("{line}:\t{:?}", first_marker(line.trim(), 14))
         )
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_examples() {
+        let expected = [(7, 19), (5, 23), (6, 23), (10, 29), (11, 26)];
+        for (line, (packet, message)) in include_str!("test.txt").lines().zip(expected) {
+            assert_eq!(first_marker(line, 4), Some(packet));
+            assert_eq!(first_marker(line, 14), Some(message));
+        }
+        assert_eq!(first_marker("abcabc", 4), None);
+        assert_eq!(first_marker("ab", 4), None);
+    }
+
+    #[test]
+    fn test_stream() {
+        let input = include_str!("test.txt").replace('\n', "");
+        let markers: Vec<_> =
+            Markers::new(input.as_bytes(), &[4, 14])
+                .collect::<Result<_, _>>()
+                .unwrap();
+
+        let mut expected = Vec::new();
+        for offset in 1..=input.len() {
+            for size in [4, 14] {
+                if offset >= size && first_marker(&input[offset - size..offset], size).is_some() {
+                    expected.push(Marker { size, offset });
+                }
+            }
+        }
+        assert_eq!(markers, expected);
+        assert_eq!(markers[0], Marker { size: 4, offset: 7 });
+    }
+
+    #[test]
+    fn test_decode() {
+        // skipped, message marker, payload of the first frame (containing the packet marker `xyz`),
+        // message marker `zpqr`, payload of the second frame
+        let frames: Vec<_> = decode(b"aaaaabcdxxyyxyzzzpqrs", 3, 4).collect();
+        assert_eq!(frames, [
+            Frame {
+                offset: 8,
+                payload: b"xxyyxyzz",
+                preamble: b"xxyy",
+                packets: vec![Packet { offset: 15, payload: b"z" }],
+            },
+            Frame {
+                offset: 20,
+                payload: b"s",
+                preamble: b"s",
+                packets: vec![],
+            },
+        ]);
+        assert_eq!(decode(b"aaaaaa", 3, 4).next(), None);
+    }
+
+    #[test]
+    fn test_alphabets() {
+        assert_eq!(first_marker("AAb1\n\n", 3), Some(4));
+        assert_eq!(first_marker_in(&[0u32, 1_000_000, 0, 7], 3), Some(4));
+        // each `é` is two bytes, which are different from each other
+        assert_eq!(first_marker("éé", 2), Some(2));
+        assert_eq!(first_marker_chars("éé", 2), None);
+        assert_eq!(first_marker_chars("ééü", 2), Some(3));
+    }
+}
@@ -1,11 +1,11 @@
-use std::collections::{BTreeMap};
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
 use std::fmt::{Display, Formatter};
 use std::mem;
-use anyhow::bail;
-use pest::iterators::{Pairs};
+use anyhow::{bail, ensure, Context};
+use pest::iterators::{Pair, Pairs};
 use pest::Parser;
 use pest_derive::Parser;
-use once_cell::sync::OnceCell;
 
 // *************************************************************************************************
 
@@ -27,16 +27,44 @@ struct LsIter<'a> {
 enum Cmd<'a> {
     Cd(&'a str),
     Ls(LsIter<'a>),
+    Mkdir(&'a str),
+    Rm {
+        name: &'a str,
+        /// Whether directories may be removed (`rm -r`)
+        recursive: bool,
+    },
+    /// Create a file with the given size, or change the size of an existing file
+    Touch {
+        name: &'a str,
+        size: usize,
+    },
+    Mv {
+        from: &'a str,
+        to: &'a str,
+    },
+    /// Contains the printed working directory, if the transcript includes it
+    Pwd(Option<&'a str>),
 }
 
 // *************************************************************************************************
 
+/// Parse the `num` pair of a file size, naming its line if it does not fit
+fn parse_size(num: Pair<'_, Rule>) -> anyhow::Result<usize> {
+    debug_assert_eq!(num.as_rule(), Rule::num);
+    num.as_str().parse()
+        .with_context(|| {
+            // `line_col` scans the input from its start, which is fine for a single error
+            let line = num.as_span().start_pos().line_col().0;
+            format!("line {line}: invalid size `{}`", num.as_str())
+        })
+}
+
 impl<'a> Iterator for LsIter<'a> {
-    type Item = LsLine<'a>;
+    type Item = anyhow::Result<LsLine<'a>>;
 
     fn next(&mut self) -> Option<Self::Item> {
         let ls_line = self.parse.next()?;
-        Some(match ls_line.as_rule() {
+        Some(Ok(match ls_line.as_rule() {
             Rule::ls_dir => {
                 let filename = ls_line.into_inner().next().unwrap().as_str();
                 LsLine {
@@ -46,7 +74,10 @@ impl<'a> Iterator for LsIter<'a> {
             }
             Rule::ls_file => {
                 let mut ls_line = ls_line.into_inner();
-                let size = Some(ls_line.next().unwrap().as_str().parse().unwrap());
+                let size = match parse_size(ls_line.next().unwrap()) {
+                    Ok(size) => Some(size),
+                    Err(e) => return Some(Err(e)),
+                };
                 let filename = ls_line.next().unwrap().as_str();
                 LsLine {
                     filename,
@@ -56,44 +87,90 @@ impl<'a> Iterator for LsIter<'a> {
             _ => {
                 panic!("Expected `ls_dir` or `ls_file`, found {ls_line:?}")
             }
-        })
+        }))
     }
 }
 
 
 impl<'a> Cmd<'a> {
-    fn parse(input: &'a str) -> impl Iterator<Item=Cmd<'a>> {
-        CmdParser::parse(Rule::file, input).expect("Failed to parse input")
-            .next().unwrap()
+    /// Parse a transcript into its commands, each with the line it starts on
+    fn parse(input: &'a str) -> anyhow::Result<impl Iterator<Item=anyhow::Result<(usize, Cmd<'a>)>>> {
+        let parse = CmdParser::parse(Rule::file, input)?;
+        // the line and byte offset of the previous command; commands are in order, so counting
+        // the newlines in between is much cheaper than calling `line_col` on each of them
+        let (mut line, mut offset) = (1, 0);
+        Ok(parse
+            .into_iter().next().unwrap()
             .into_inner()
             .filter(|cmd| cmd.as_rule() != Rule::EOI)
-            .map(|cmd| {
+            .map(move |cmd| {
                 debug_assert_eq!(cmd.as_rule(), Rule::cmd);
+                let start = cmd.as_span().start();
+                line += input[offset..start].matches('\n').count();
+                offset = start;
                 let cmd = cmd.into_inner().next().unwrap();
 
                 let rule = cmd.as_rule();
-                match rule {
+                let mut args = cmd.clone().into_inner();
+                let mut next_arg = || args.next().unwrap();
+                let cmd = match rule {
                     Rule::cd_cmd => {
-                        let dirname = cmd.into_inner().next().unwrap().as_str();
-                        Cmd::Cd(dirname)
+                        Cmd::Cd(next_arg().as_str())
                     }
                     Rule::ls_cmd => {
                         Cmd::Ls(LsIter {
                             parse: cmd.into_inner()
                         })
                     }
+                    Rule::mkdir_cmd => {
+                        Cmd::Mkdir(next_arg().as_str())
+                    }
+                    Rule::rm_cmd => {
+                        let args: Vec<_> = cmd.into_inner().collect();
+                        Cmd::Rm {
+                            name: args.last().unwrap().as_str(),
+                            recursive: args.len() == 2,
+                        }
+                    }
+                    Rule::touch_cmd => {
+                        let size = parse_size(next_arg())?;
+                        Cmd::Touch { size, name: next_arg().as_str() }
+                    }
+                    Rule::mv_cmd => {
+                        // a `word` is either quoted or unquoted, get the contents either way
+                        let mut next_word = || next_arg().into_inner().next().unwrap().as_str();
+                        Cmd::Mv { from: next_word(), to: next_word() }
+                    }
+                    Rule::pwd_cmd => {
+                        Cmd::Pwd(cmd.into_inner().next().map(|path| path.as_str()))
+                    }
                     other => {
                         panic!("Unexpected rule ({other:?}) in {cmd:?}")
                     }
-                }
-            })
+                };
+                Ok((line, cmd))
+            }))
+    }
+}
+
+impl Cmd<'_> {
+    fn name(&self) -> &'static str {
+        match self {
+            Cmd::Cd(_) => "cd",
+            Cmd::Ls(_) => "ls",
+            Cmd::Mkdir(_) => "mkdir",
+            Cmd::Rm { .. } => "rm",
+            Cmd::Touch { .. } => "touch",
+            Cmd::Mv { .. } => "mv",
+            Cmd::Pwd(_) => "pwd",
+        }
     }
 }
 
 // *************************************************************************************************
 
 
-enum DirEntry<'a> {
+pub enum DirEntry<'a> {
     File {
         size: usize
     },
@@ -101,9 +178,24 @@ enum DirEntry<'a> {
 }
 
 #[derive(Default)]
-struct Directory<'a> {
+pub struct Directory<'a> {
     children: BTreeMap<&'a str, DirEntry<'a>>,
-    size: OnceCell<usize>,
+    /// The total size of all (nested) files, which is kept up to date on every change
+    size: usize,
+}
+
+/// The size of a disk, and how much free space it should have
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Disk {
+    pub capacity: usize,
+    pub required: usize,
+}
+
+/// Directories to delete, none of which is nested in another, and how much space that frees
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Deletion {
+    pub dirs: Vec<String>,
+    pub freed: usize,
 }
 
 struct Cursor<'a> {
@@ -114,12 +206,21 @@ struct Cursor<'a> {
 
 // *************************************************************************************************
 
+/// `.` and `..` have a special meaning and `/` separates the parts of a path, so no entry can be
+/// named `.` or `..`, or have a `/` in its name
+fn check_name(name: &str) -> anyhow::Result<()> {
+    ensure!(
+        !matches!(name, "." | "..") && !name.contains('/'),
+        "`{name}` is not a valid name for a file or directory"
+    );
+    Ok(())
+}
+
 impl<'a> Cursor<'a> {
     /// Tries to go up a single directory
     /// Returns `false` if the current dir is already the root
     fn go_up(&mut self) -> bool {
         if let Some((mut dir, name)) = self.parents.pop() {
-            self.current.size.take();
             mem::swap(&mut dir, &mut self.current);
             self.current.children.insert(name, DirEntry::Dir(dir));
             true
@@ -128,6 +229,15 @@ impl<'a> Cursor<'a> {
         }
     }
 
+    /// Replace `old` by `new` in the size of the current directory and its ancestors, except for
+    /// the `skip` outermost ones. Takes time linear in the depth of the current directory
+    fn resize(&mut self, skip: usize, old: usize, new: usize) {
+        let ancestors = self.parents.iter_mut().skip(skip).map(|(dir, _)| dir);
+        for dir in ancestors.chain([&mut self.current]) {
+            dir.size = dir.size - old + new;
+        }
+    }
+
     /// Go to the root directory
     fn go_to_root(&mut self) {
         while self.go_up() {}
@@ -165,6 +275,128 @@ impl<'a> Cursor<'a> {
         Ok(())
     }
 
+    /// Change directory along a `/`-separated path, which is absolute if it starts with `/`
+    fn cd(&mut self, path: &'a str) -> anyhow::Result<()> {
+        if path.starts_with('/') {
+            self.go_to_root();
+        }
+        for dirname in path.split('/').filter(|&d| !d.is_empty() && d != ".") {
+            self.enter(dirname)?;
+        }
+        Ok(())
+    }
+
+    /// The absolute path of the current directory
+    fn pwd(&self) -> String {
+        if self.parents.is_empty() {
+            return "/".to_string();
+        }
+        self.parents.iter()
+            .map(|(_, name)| format!("/{name}"))
+            .collect()
+    }
+
+    fn mkdir(&mut self, name: &'a str) -> anyhow::Result<()> {
+        check_name(name)?;
+        ensure!(!self.current.children.contains_key(name), "{name} already exists");
+        self.current.children.insert(name, DirEntry::Dir(Box::default()));
+        Ok(())
+    }
+
+    /// Remove a file, or (if `recursive`) a directory with all its contents
+    fn rm(&mut self, name: &'a str, recursive: bool) -> anyhow::Result<()> {
+        match self.current.children.get(name) {
+            None => {
+                bail!("{name} does not exist")
+            }
+            Some(DirEntry::Dir(_)) if !recursive => {
+                bail!("{name} is a directory, use `rm -r` to remove it")
+            }
+            Some(_) => {
+                let removed = self.current.children.remove(name).unwrap();
+                self.resize(0, removed.size(), 0);
+                Ok(())
+            }
+        }
+    }
+
+    fn touch(&mut self, name: &'a str, size: usize) -> anyhow::Result<()> {
+        check_name(name)?;
+        match self.current.children.get_mut(name) {
+            Some(DirEntry::Dir(_)) => {
+                bail!("{name} is a directory, expected a file")
+            }
+            Some(DirEntry::File { size: old }) => {
+                let old = mem::replace(old, size);
+                self.resize(0, old, size);
+            }
+            None => {
+                self.current.children.insert(name, DirEntry::File { size });
+                self.resize(0, 0, size);
+            }
+        }
+        Ok(())
+    }
+
+    /// Move the entry `from` into the directory `to`, which may be `..`, `/` or a sub directory,
+    /// or rename it to `to` if there is no such directory
+    fn mv(&mut self, from: &'a str, to: &'a str) -> anyhow::Result<()> {
+        ensure!(self.current.children.contains_key(from), "{from} does not exist");
+        ensure!(from != to, "cannot move {from} onto itself");
+        if !matches!(to, ".." | "/") && !self.current.children.contains_key(to) {
+            // `from` will be renamed to `to`
+            check_name(to)?;
+        }
+
+        let into_child = matches!(self.current.children.get(to), Some(DirEntry::Dir(_)));
+        let mut entry = self.current.children.remove(from);
+        let moved = entry.as_ref().unwrap().size();
+        let target = match to {
+            ".." | "/" => {
+                let parent = if to == ".." { self.parents.last_mut() } else { self.parents.first_mut() };
+                match parent {
+                    // the child we are in is not part of `dir.children` while we are in it
+                    Some((_, child)) if *child == from => {
+                        Err("it already contains an entry with that name")
+                    }
+                    Some((dir, _)) => Ok((dir.as_mut(), from)),
+                    None => Err("the current directory is the root"),
+                }
+            }
+            _ => {
+                match self.current.children.get_mut(to) {
+                    Some(DirEntry::Dir(dir)) => Ok((dir.as_mut(), from)),
+                    Some(DirEntry::File { .. }) => Err("it is an existing file"),
+                    None => Ok((self.current.as_mut(), to)),
+                }
+            }
+        };
+
+        let error = match target {
+            Ok((dir, name)) if !dir.children.contains_key(name) => {
+                dir.children.insert(name, entry.take().unwrap());
+                if into_child {
+                    dir.size += moved;
+                }
+                None
+            }
+            Ok(_) => Some("it already contains an entry with that name"),
+            Err(error) => Some(error),
+        };
+        if let Some(error) = error {
+            self.current.children.insert(from, entry.unwrap());
+            bail!("cannot move {from} to {to}, {error}")
+        }
+
+        // the moved entry no longer counts towards the directories it left
+        match to {
+            ".." => self.resize(self.parents.len(), moved, 0),
+            "/" => self.resize(1, moved, 0),
+            _ => {}
+        }
+        Ok(())
+    }
+
     fn into_dir(mut self) -> Box<Directory<'a>> {
         self.go_to_root();
         self.current
@@ -200,24 +432,73 @@ impl<'a> Cursor<'a> {
         }
     }
 
-    fn build(input: &'a str) -> Self {
-        let mut cursor = Self::new();
-        for cmd in Cmd::parse(input) {
-            match cmd {
-                Cmd::Cd(dir) => {
-                    cursor.enter(dir).unwrap();
+    fn execute(&mut self, cmd: Cmd<'a>) -> anyhow::Result<()> {
+        match cmd {
+            Cmd::Cd(path) => {
+                self.cd(path)?;
+            }
+            Cmd::Ls(entries) => {
+                // keep what we already know about listed directories
+                for entry in entries {
+                    let entry = entry?;
+                    check_name(entry.filename)?;
+                    if let Entry::Vacant(vacant) = self.current.children.entry(entry.filename) {
+                        vacant.insert(entry.dir_entry());
+                        self.resize(0, 0, entry.size.unwrap_or(0));
+                    }
                 }
-                Cmd::Ls(entries) => {
-                    for entry in entries {
-                        cursor.current.children.insert(
-                            entry.filename,
-                            entry.dir_entry(),
-                        );
+            }
+            Cmd::Mkdir(name) => {
+                self.mkdir(name)?;
+            }
+            Cmd::Rm { name, recursive } => {
+                self.rm(name, recursive)?;
+            }
+            Cmd::Touch { name, size } => {
+                self.touch(name, size)?;
+            }
+            Cmd::Mv { from, to } => {
+                self.mv(from, to)?;
+            }
+            Cmd::Pwd(printed) => {
+                let pwd = self.pwd();
+                if let Some(printed) = printed {
+                    ensure!(printed == pwd, "pwd printed {printed}, but the current directory is {pwd}");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Replay a transcript, failing on the first invalid command.
+    ///
+    /// The transcript has to establish the current directory before anything else, either by
+    /// changing to an absolute path, or by a `pwd` with its output
+    fn build(input: &'a str) -> anyhow::Result<Self> {
+        let mut cursor = Self::new();
+        let mut located = false;
+        for cmd in Cmd::parse(input)? {
+            let (line, cmd) = cmd?;
+            if !located {
+                match cmd {
+                    Cmd::Cd(path) | Cmd::Pwd(Some(path)) if path.starts_with('/') => {
+                        cursor.cd(path)?;
+                        located = true;
+                        continue;
+                    }
+                    _ => {
+                        bail!(
+                            "line {line}: `{}` depends on the current directory, which is unknown at \
+                            this point; the transcript should start with `$ cd /` (or another \
+                            absolute path)",
+                            cmd.name()
+                        )
                     }
                 }
             }
+            cursor.execute(cmd).with_context(|| format!("line {line}"))?;
         }
-        cursor
+        Ok(cursor)
     }
 }
 
@@ -247,11 +528,7 @@ impl Display for DirectoryDisplay<'_, '_> {
                     writeln!(f, "(file, size={size})")?;
                 }
                 DirEntry::Dir(dir) => {
-                    write!(f, "(dir")?;
-                    if let Some(size) = dir.size.get() {
-                        write!(f, ", size={size}")?;
-                    }
-                    write!(f, ")\n{}", dir.display(self.indent + 2))?;
+                    write!(f, "(dir, size={})\n{}", dir.size, dir.display(self.indent + 2))?;
                 }
             }
         }
@@ -261,11 +538,7 @@ impl Display for DirectoryDisplay<'_, '_> {
 
 impl Display for Directory<'_> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "(dir")?;
-        if let Some(size) = self.size.get() {
-            write!(f, ", size={size}")?;
-        }
-        write!(f, ")\n{}", self.display(2))?;
+        write!(f, "(dir, size={})\n{}", self.size, self.display(2))?;
         Ok(())
     }
 }
@@ -273,27 +546,48 @@ impl Display for Directory<'_> {
 // *************************************************************************************************
 
 impl<'a, 'b> DirEntry<'a> {
-    fn as_dir(&'b self) -> Option<&'b Directory<'a>> {
+    pub fn as_dir(&'b self) -> Option<&'b Directory<'a>> {
         if let DirEntry::Dir(dir) = self {
             Some(dir)
         } else {
             None
         }
     }
+
+    /// The size of a file, or the total size of a directory
+    pub fn size(&self) -> usize {
+        match self {
+            DirEntry::File { size } => *size,
+            DirEntry::Dir(dir) => dir.size(),
+        }
+    }
 }
 
 impl<'a> Directory<'a> {
-    fn size(&self) -> usize {
-        *self.size.get_or_init(|| {
-            self.children.values()
-                .map(|entry| {
-                    match entry {
-                        DirEntry::File { size } => *size,
-                        DirEntry::Dir(dir) => dir.size(),
-                    }
-                })
-                .sum()
-        })
+    /// Replay a transcript, returning the root directory
+    pub fn from_transcript(input: &'a str) -> anyhow::Result<Box<Self>> {
+        Ok(Cursor::build(input)?.into_dir())
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Recompute all sizes from scratch, and check that they match the maintained ones
+    pub fn check_sizes(&self) -> anyhow::Result<()> {
+        self.recompute_size("/").map(|_| ())
+    }
+
+    fn recompute_size(&self, path: &str) -> anyhow::Result<usize> {
+        let mut total = 0;
+        for (name, entry) in &self.children {
+            total += match entry {
+                DirEntry::File { size } => *size,
+                DirEntry::Dir(dir) => dir.recompute_size(&format!("{path}{name}/"))?,
+            };
+        }
+        ensure!(total == self.size, "{path} has size {}, but its contents add up to {total}", self.size);
+        Ok(total)
     }
 
     /// Flatten the directory structure, returning an iterator over all (nested) sub directories
@@ -320,38 +614,247 @@ impl<'a> Directory<'a> {
     }
 
     /// Find the size of the smallest (nested) sub-directory that is at least as big as `threshold`
-    fn find_smallest<'b>(&'b self, threshold: usize) -> usize {
+    fn find_smallest(&self, threshold: usize) -> anyhow::Result<usize> {
         self.sub_dirs_flatten()
             .map(|(_, d)| d.size())
             .filter(|&s| s >= threshold)
             .min()
-            .unwrap_or(0)
+            .with_context(|| format!("There is no directory of at least {threshold}"))
+    }
+}
+
+// *************************************************************************************************
+
+/// The number of components of a path, so `/` has depth 0 and `/a/e` has depth 2
+pub fn depth(path: &str) -> usize {
+    path.split('/').filter(|name| !name.is_empty()).count()
+}
+
+/// Whether `text` matches `pattern`, where `?` matches any single character and `*` any sequence
+/// of characters, both within a single path component, and `**` matches across components
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        // `**/` matches zero or more whole components
+        ['*', '*', '/', rest @ ..] => {
+            (0..=text.len())
+                .filter(|&i| i == 0 || text[i - 1] == '/')
+                .any(|i| glob_match(rest, &text[i..]))
+        }
+        ['*', '*', rest @ ..] => {
+            (0..=text.len()).any(|i| glob_match(rest, &text[i..]))
+        }
+        ['*', rest @ ..] => {
+            (0..=text.len())
+                .take_while(|&i| i == 0 || text[i - 1] != '/')
+                .any(|i| glob_match(rest, &text[i..]))
+        }
+        ['?', rest @ ..] => {
+            matches!(text, [c, text @ ..] if *c != '/' && glob_match(rest, text))
+        }
+        [p, rest @ ..] => {
+            matches!(text, [c, text @ ..] if c == p && glob_match(rest, text))
+        }
+    }
+}
+
+impl<'a> Directory<'a> {
+    /// The directory at the given absolute path, e.g. `/a/e`, or `/` for this directory itself
+    pub fn get_dir(&self, path: &str) -> Option<&Directory<'a>> {
+        let mut dir = self;
+        for name in path.strip_prefix('/')?.split('/').filter(|name| !name.is_empty()) {
+            dir = dir.children.get(name)?.as_dir()?;
+        }
+        Some(dir)
+    }
+
+    /// The file or directory at the given absolute path, e.g. `/a/e`
+    pub fn get(&self, path: &str) -> Option<&DirEntry<'a>> {
+        let path = path.strip_prefix('/')?.trim_end_matches('/');
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        self.get_dir(&format!("/{parent}"))?.children.get(name)
+    }
+
+    /// All (nested) entries, each with its absolute path, with every directory before its contents
+    pub fn entries<'b>(&'b self) -> Box<dyn Iterator<Item=(String, &'b DirEntry<'a>)> + 'b> {
+        self.entries_in(String::new())
+    }
+
+    fn entries_in<'b>(&'b self, prefix: String) -> Box<dyn Iterator<Item=(String, &'b DirEntry<'a>)> + 'b> {
+        let iter =
+            self.children.iter()
+                .flat_map(move |(name, entry)| {
+                    let path = format!("{prefix}/{name}");
+                    let nested = entry.as_dir().map(|dir| dir.entries_in(path.clone()));
+                    [(path, entry)].into_iter().chain(nested.into_iter().flatten())
+                });
+        Box::new(iter)
+    }
+
+    /// All entries that match a glob pattern (see [glob_match]). A pattern that contains a `/` is
+    /// matched against the absolute path, any other pattern only against the name of the entry
+    pub fn find<'b>(&'b self, pattern: &'b str) -> impl Iterator<Item=(String, &'b DirEntry<'a>)> + 'b {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let full_path = pattern.contains(&'/');
+        self.entries()
+            .filter(move |(path, _)| {
+                let text = if full_path { path } else { path.rsplit('/').next().unwrap() };
+                glob_match(&pattern, &text.chars().collect::<Vec<_>>())
+            })
+    }
+
+    /// Like `du`: the total size of this directory and each (nested) sub directory that is at most
+    /// `max_depth` levels deep (or of all of them, if `max_depth` is `None`), parents first
+    pub fn du(&self, max_depth: Option<usize>) -> Vec<(String, usize)> {
+        let subs =
+            self.entries()
+                .filter(|(path, entry)| {
+                    entry.as_dir().is_some() && max_depth.is_none_or(|max| depth(path) <= max)
+                })
+                .map(|(path, entry)| (path, entry.size()));
+        [("/".to_string(), self.size())].into_iter().chain(subs).collect()
+    }
+}
+
+// *************************************************************************************************
+
+impl Disk {
+    /// The puzzle's disk: 70000000 in total, of which 30000000 should be free
+    pub const PUZZLE: Disk = Disk { capacity: 70_000_000, required: 30_000_000 };
+
+    /// How much space has to be freed when `used` is in use (`0` if enough space is free already).
+    /// Fails if `used` or the requirement exceed the capacity
+    pub fn to_free(&self, used: usize) -> anyhow::Result<usize> {
+        let free = self.capacity.checked_sub(used)
+            .with_context(|| format!("{used} is in use, which exceeds the capacity of {}", self.capacity))?;
+        ensure!(
+            self.required <= self.capacity,
+            "{} should be free, which exceeds the capacity of {}", self.required, self.capacity
+        );
+        Ok(self.required.saturating_sub(free))
+    }
+}
+
+/// Branch-and-bound search over the sub directories in pre-order, see [Directory::plan_deletion]
+struct DeletionSearch {
+    sizes: Vec<usize>,
+    /// `ends[i]` is the index of the first directory after all of `i`'s sub directories
+    ends: Vec<usize>,
+    /// `reach[k][i]` is the most space that can be freed by deleting at most `k` directories
+    /// from `i` on
+    reach: Vec<Vec<usize>>,
+    needed: usize,
+    chosen: Vec<usize>,
+    /// The choice that frees the least space so far: `(freed, chosen)`
+    best: Option<(usize, Vec<usize>)>,
+}
+
+impl DeletionSearch {
+    fn new(sizes: Vec<usize>, ends: Vec<usize>, needed: usize) -> Self {
+        let reach = vec![vec![0; sizes.len() + 1]];
+        Self { sizes, ends, reach, needed, chosen: Vec::new(), best: None }
+    }
+
+    /// The fewest directories that can free enough space, computing `reach` up to that number.
+    /// Returns `None` if deleting all directories does not free enough
+    fn min_count(&mut self) -> Option<usize> {
+        loop {
+            let last = self.reach.last().unwrap();
+            if last[0] >= self.needed {
+                return Some(self.reach.len() - 1);
+            }
+
+            let mut next = vec![0; self.sizes.len() + 1];
+            for i in (0..self.sizes.len()).rev() {
+                next[i] = next[i + 1].max(self.sizes[i] + last[self.ends[i]]);
+            }
+            if next == *last {
+                return None;
+            }
+            self.reach.push(next);
+        }
+    }
+
+    /// Search for the choice of at most `k` directories from `i` on that frees the least space
+    fn search(&mut self, i: usize, k: usize, freed: usize) {
+        if freed >= self.needed {
+            if self.best.as_ref().is_none_or(|(best, _)| freed < *best) {
+                self.best = Some((freed, self.chosen.clone()));
+            }
+            return;
+        }
+        if freed + self.reach[k][i] < self.needed {
+            return;
+        }
+
+        // delete directory `i`, which deletes its sub directories as well
+        self.chosen.push(i);
+        self.search(self.ends[i], k - 1, freed + self.sizes[i]);
+        self.chosen.pop();
+
+        // keep it, but maybe delete some of its sub directories
+        self.search(i + 1, k, freed);
+    }
+}
+
+impl Directory<'_> {
+    /// The fewest (nested) sub directories to delete to free at least `needed`, where, among all
+    /// ways to do so with that many directories, the freed space is as small as possible.
+    /// Fails if deleting all sub directories would not free enough.
+    ///
+    /// Choosing the directories is a knapsack problem, which is solved by branch and bound, so this
+    /// may take long if many directories have to be deleted
+    pub fn plan_deletion(&self, needed: usize) -> anyhow::Result<Deletion> {
+        let dirs: Vec<_> =
+            self.entries()
+                .filter_map(|(path, entry)| Some((path, entry.as_dir()?.size())))
+                .collect();
+
+        // in pre-order, the sub directories of a directory directly follow it
+        let ends: Vec<_> =
+            dirs.iter()
+                .enumerate()
+                .map(|(i, (path, _))| {
+                    let prefix = format!("{path}/");
+                    i + 1 + dirs[i + 1..].iter().take_while(|(p, _)| p.starts_with(&prefix)).count()
+                })
+                .collect();
+        let mut search = DeletionSearch::new(dirs.iter().map(|(_, size)| *size).collect(), ends, needed);
+
+        let Some(k) = search.min_count() else {
+            bail!("Deleting directories frees at most {}, but {needed} is needed", search.reach.last().unwrap()[0])
+        };
+        search.search(0, k, 0);
+
+        let (freed, chosen) = search.best.expect("`k` directories can free enough");
+        Ok(Deletion {
+            dirs: chosen.into_iter().map(|i| dirs[i].0.clone()).collect(),
+            freed,
+        })
     }
 }
 
 // *************************************************************************************************
 
 #[aoc(day7, part1)]
-fn part1(input: &str) -> usize {
-    let d = Cursor::build(input).into_dir();
+fn part1(input: &str) -> anyhow::Result<usize> {
+    let d = Cursor::build(input)?.into_dir();
     println!("{d}");
 
-    d.sum_sizes(100_000)
+    Ok(d.sum_sizes(100_000))
 }
 
 #[aoc(day7, part2)]
-fn part2(input: &str) -> usize {
-    let d = Cursor::build(input).into_dir();
+fn part2(input: &str) -> anyhow::Result<usize> {
+    let d = Cursor::build(input)?.into_dir();
 
-    let used = d.size();
-    let free = 70_000_000 - used;
-    let needed = 30_000_000 - free;
+    let needed = Disk::PUZZLE.to_free(d.size())?;
 
     println!("{d}");
     println!("need {needed}");
 
-    for (name, sub) in d.sub_dirs_flatten() {
-        println!("{name}: {}", sub.size())
+    for (path, size) in d.du(None) {
+        println!("{size}\t{path}")
     }
 
     d.find_smallest(needed)
@@ -361,5 +864,173 @@ fn part2(input: &str) -> usize {
 
 
 pub fn main() {
-    println!("{}", part2(include_str!("test.txt")))
-}
\ No newline at end of file
+    let input = include_str!("test.txt");
+    println!("{}", part2(input).unwrap());
+
+    // the planner searches all combinations of directories, so keep it out of `part2`
+    let d = Directory::from_transcript(input).unwrap();
+    let needed = Disk::PUZZLE.to_free(d.size()).unwrap();
+    println!("{:?}", d.plan_deletion(needed).unwrap());
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_example() {
+        assert_eq!(part1(include_str!("test.txt")).unwrap(), 95437);
+        assert_eq!(part2(include_str!("test.txt")).unwrap(), 24933642);
+    }
+
+    #[test]
+    fn test_queries() {
+        let d = Directory::from_transcript(include_str!("test.txt")).unwrap();
+        assert_eq!(d.get("/a/e").map(DirEntry::size), Some(584));
+        assert_eq!(d.get("/d/k").map(DirEntry::size), Some(7214296));
+        assert!(d.get("/a/x").is_none());
+        assert!(d.get_dir("/b.txt").is_none());
+        assert_eq!(d.get_dir("/").map(Directory::size), Some(48381165));
+
+        let paths = |pattern| d.find(pattern).map(|(path, _)| path).collect::<Vec<_>>();
+        assert_eq!(paths("*.txt"), ["/b.txt"]);
+        assert_eq!(paths("d.*"), ["/d/d.ext", "/d/d.log"]);
+        assert_eq!(paths("/**/?"), ["/a", "/a/e", "/a/e/i", "/a/f", "/a/g", "/d", "/d/j", "/d/k"]);
+        assert_eq!(paths("/a/*"), ["/a/e", "/a/f", "/a/g", "/a/h.lst"]);
+        assert_eq!(paths("/**/i"), ["/a/e/i"]);
+
+        let du: Vec<_> = d.du(Some(1)).into_iter().map(|(path, size)| format!("{size} {path}")).collect();
+        assert_eq!(du, ["48381165 /", "94853 /a", "24933642 /d"]);
+        assert_eq!(depth("/a/e/i"), 3);
+    }
+
+    #[test]
+    fn test_deletion() {
+        let d = Directory::from_transcript(include_str!("test.txt")).unwrap();
+        let needed = Disk::PUZZLE.to_free(d.size()).unwrap();
+        assert_eq!(needed, 8381165);
+
+        let plan = d.plan_deletion(needed).unwrap();
+        assert_eq!((plan.dirs, plan.freed), (vec!["/d".to_string()], 24933642));
+        let plan = d.plan_deletion(25_000_000).unwrap();
+        assert_eq!((plan.dirs, plan.freed), (vec!["/a".to_string(), "/d".to_string()], 25028495));
+        assert_eq!(d.plan_deletion(0).unwrap().dirs, Vec::<String>::new());
+        assert!(d.plan_deletion(30_000_000).is_err());
+
+        let small = Disk { capacity: 40_000_000, required: 10 };
+        assert!(small.to_free(d.size()).is_err());
+        let big = Disk { capacity: 100_000_000, required: 10 };
+        assert_eq!(big.to_free(d.size()).unwrap(), 0);
+        assert!(Disk { capacity: 10, required: 20 }.to_free(0).is_err());
+    }
+
+    #[test]
+    fn test_mutations() {
+        let transcript = "\
+$ cd /
+$ mkdir a
+$ touch 10 x
+$ cd a
+$ touch 5 y
+$ mkdir b
+$ mv y b
+$ pwd
+/a
+$ mv b ..
+$ cd ..
+$ mv x z
+$ touch 20 z
+$ rm -r a
+$ ls
+dir b
+";
+        let d = Cursor::build(transcript).unwrap().into_dir();
+        assert_eq!(d.children.keys().copied().collect::<Vec<_>>(), ["b", "z"]);
+        assert_eq!(d.size(), 25);
+    }
+
+    #[test]
+    fn test_names() {
+        let transcript = "\
+$ cd /home/some user
+$ ls
+dir Ünïcödé
+123 file-name_with spaces.txt
+$ mv 'file-name_with spaces.txt' Ünïcödé
+$ pwd
+/home/some user
+";
+        let d = Cursor::build(transcript).unwrap().into_dir();
+        assert_eq!(d.size(), 123);
+        assert_eq!(d.to_string().lines().nth(3), Some("      - Ünïcödé (dir, size=123)"));
+
+        let error = Cursor::build("$ ls\n1 a\n").err().unwrap();
+        assert!(error.to_string().contains("current directory"), "{error}");
+        assert!(Cursor::build("$ cd ..\n").is_err());
+    }
+
+    #[test]
+    fn test_sizes() {
+        let transcript = "\
+$ cd /
+$ mkdir a
+$ cd a
+$ mkdir b
+$ cd b
+$ ls
+dir c
+7 x
+$ touch 3 y
+$ mv y c
+$ mv x /
+$ cd c
+$ touch 10 y
+$ mv y ..
+$ cd /
+$ mv x a
+$ touch 2 x
+$ rm -r a
+";
+        // check after every command, the sizes of all ancestors have to be kept up to date
+        let lines: Vec<_> = transcript.lines().collect();
+        for n in 1..=lines.len() {
+            let prefix = lines[..n].join("\n");
+            if lines.get(n).is_some_and(|line| !line.starts_with('$')) {
+                continue;
+            }
+            let d = Directory::from_transcript(&prefix).unwrap();
+            d.check_sizes().unwrap();
+        }
+        assert_eq!(Directory::from_transcript(transcript).unwrap().size(), 2);
+    }
+
+    #[test]
+    fn test_invalid() {
+        for transcript in [
+            "$ cd /\n$ rm a\n",
+            "$ cd /\n$ mkdir a\n$ rm a\n",
+            "$ cd /\n$ mkdir a\n$ mkdir a\n",
+            "$ cd /\n$ mkdir a\n$ touch 1 a\n",
+            "$ cd /\n$ touch 1 a\n$ mv a ..\n",
+            "$ cd /\n$ mkdir a\n$ cd a\n$ pwd\n/b\n",
+            "$ cd /\n$ mkdir a\n$ cd a\n$ mkdir a\n$ mv a ..\n",
+            "$ cd /\n$ touch 1 x\n$ mv x a/b\n",
+            "$ cd /\n$ touch 1 x\n$ mv x .\n",
+            "$ cd /\n$ touch 99999999999999999999999 x\n",
+            "$ cd /\n$ ls\n99999999999999999999999 x\n",
+            "$ cd /\n$ touch 1 x\n$ rmdir x\n",
+            "$ cd /\n$ mkdir foo\n$ cdfoo\n",
+            "$ cd /\n$ mkdirx\n",
+            "$ cd /\n$ touch1 x\n",
+            "$ cd /\n$ touch 1x\n",
+            "$ cd /\n$ touch 1 x\n$ mvx y\n",
+            "$ cd /\n$ ls\ndirx\n",
+            "$ cd /\n$ ls\n12x\n",
+        ] {
+            assert!(Cursor::build(transcript).is_err(), "{transcript}");
+        }
+
+        let error = |transcript| format!("{:#}", Directory::from_transcript(transcript).err().unwrap());
+        assert!(error("$ cd /\n$ ls\ndir a\n1 b\n$ rm c\n").starts_with("line 5: "));
+        assert!(error("$ cd /\n$ ls\ndir a\n99999999999999999999999 b\n").contains("line 4: invalid size"));
+    }
+}
@@ -2,7 +2,6 @@
 extern crate aoc_runner_derive;
 
 #[allow(dead_code)]
-
 pub mod day1;
 pub mod day2;
 pub mod day3;